@@ -0,0 +1,253 @@
+//! BlueZ management socket, through the bluez crate.
+//!
+//! The management API only covers the controller: discovery, pairing and
+//! connections. It has no GATT, so every characteristic operation fails.
+
+use super::{Advertisement, BackendEvent, BleBackend, GattService};
+use crate::ble_uuid::BleUuid;
+use crate::error::Error;
+use async_std::future;
+use async_std::task;
+use bluez::client::{AddressType, AddressTypeFlag, BlueZClient};
+use bluez::interface::controller::Controller;
+use bluez::interface::event::Event;
+use bluez::Address;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// EIR 数据里的字段类型
+const EIR_UUID16_SOME: u8 = 0x02;
+const EIR_UUID16_ALL: u8 = 0x03;
+const EIR_UUID32_SOME: u8 = 0x04;
+const EIR_UUID32_ALL: u8 = 0x05;
+const EIR_UUID128_SOME: u8 = 0x06;
+const EIR_UUID128_ALL: u8 = 0x07;
+const EIR_NAME_SHORT: u8 = 0x08;
+const EIR_NAME_COMPLETE: u8 = 0x09;
+
+/// Fields of the extended inquiry response data as (type, data)
+fn eir_fields(eir_data: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut fields = vec![];
+    let mut rest = eir_data;
+    while rest.len() > 1 {
+        let len = rest[0] as usize;
+        if len == 0 || len >= rest.len() {
+            break;
+        }
+        fields.push((rest[1], &rest[2..=len]));
+        rest = &rest[len + 1..];
+    }
+    fields
+}
+
+/// Local name from the extended inquiry response data
+fn eir_name(eir_data: &[u8]) -> Option<String> {
+    eir_fields(eir_data)
+        .into_iter()
+        .find(|(field, _)| *field == EIR_NAME_SHORT || *field == EIR_NAME_COMPLETE)
+        .map(|(_, data)| String::from_utf8_lossy(data).to_string())
+}
+
+/// Advertised service UUIDs from the extended inquiry response data
+fn eir_services(eir_data: &[u8]) -> Vec<BleUuid> {
+    let mut services = vec![];
+    for (field, data) in eir_fields(eir_data) {
+        match field {
+            EIR_UUID16_SOME | EIR_UUID16_ALL => services.extend(
+                data.chunks_exact(2)
+                    .map(|b| BleUuid::from_u16(u16::from_le_bytes([b[0], b[1]]))),
+            ),
+            EIR_UUID32_SOME | EIR_UUID32_ALL => services.extend(
+                data.chunks_exact(4)
+                    .map(|b| BleUuid::from_u32(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))),
+            ),
+            EIR_UUID128_SOME | EIR_UUID128_ALL => services.extend(data.chunks_exact(16).map(|b| {
+                let mut bytes = [0; 16];
+                bytes.copy_from_slice(b);
+                BleUuid::from_le_bytes(bytes)
+            })),
+            _ => {}
+        }
+    }
+    services
+}
+
+fn not_supported(operation: &str) -> Error {
+    Error::Backend(format!(
+        "{} is not supported by the bluez management API",
+        operation
+    ))
+}
+
+fn mgmt(e: bluez::Error) -> Error {
+    Error::Backend(e.to_string())
+}
+
+pub struct BluezBackend {
+    client: BlueZClient<'static>,
+    controller: Controller,
+    /// Address type of every device seen, the commands need it
+    devices: HashMap<String, (Address, AddressType, Advertisement)>,
+}
+
+impl BluezBackend {
+    /// Use the controller named `adapter`, e.g. "hci1", or the first one
+    pub fn new(adapter: Option<&str>) -> Result<BluezBackend, Error> {
+        let mut client = BlueZClient::new().map_err(mgmt)?;
+        let controller = task::block_on(client.get_controller_list())
+            .map_err(mgmt)?
+            .into_iter()
+            .find(|c| adapter.is_none_or(|name| c.to_string() == name))
+            .ok_or_else(|| Error::AdapterNotFound(adapter.unwrap_or("default").to_string()))?;
+        Ok(BluezBackend {
+            client,
+            controller,
+            devices: HashMap::new(),
+        })
+    }
+
+    fn device(&self, device: &str) -> Result<(Address, AddressType), Error> {
+        self.devices
+            .get(device)
+            .map(|(address, address_type, _)| (*address, *address_type))
+            .ok_or_else(|| Error::DeviceNotFound(device.to_string()))
+    }
+
+    /// Process one kernel event, `None` if it is of no interest
+    fn process(&mut self, timeout: Duration) -> Result<Option<BackendEvent>, Error> {
+        let response = match task::block_on(future::timeout(timeout, self.client.process())) {
+            Ok(response) => response.map_err(mgmt)?,
+            Err(_) => return Ok(None),
+        };
+
+        let event = match response.event {
+            Event::DeviceFound {
+                address,
+                address_type,
+                rssi,
+                eir_data,
+                ..
+            } => {
+                let advertisement = Advertisement {
+                    id: address.to_string(),
+                    address: address.to_string(),
+                    name: eir_name(&eir_data),
+                    rssi: Some(rssi as i16),
+                    services: eir_services(&eir_data),
+                    paired: false,
+                    trusted: false,
+                };
+                self.devices.insert(
+                    advertisement.id.clone(),
+                    (address, address_type, advertisement.clone()),
+                );
+                BackendEvent::Discovered(advertisement)
+            }
+            Event::DeviceConnected { address, .. } => BackendEvent::Connected {
+                device: address.to_string(),
+                connected: true,
+            },
+            Event::DeviceDisconnected { address, .. } => BackendEvent::Connected {
+                device: address.to_string(),
+                connected: false,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+impl BleBackend for BluezBackend {
+    fn scan(&mut self, timeout: Duration) -> Result<Vec<Advertisement>, Error> {
+        self.scan_until(timeout, &mut |_| false)
+    }
+
+    fn scan_until(
+        &mut self,
+        timeout: Duration,
+        done: &mut dyn FnMut(&Advertisement) -> bool,
+    ) -> Result<Vec<Advertisement>, Error> {
+        let address_types = AddressTypeFlag::LEPublic | AddressTypeFlag::LERandom;
+        task::block_on(self.client.start_discovery(self.controller, address_types))
+            .map_err(mgmt)?;
+
+        let start = Instant::now();
+        let mut found = vec![];
+        while let Some(left) = timeout.checked_sub(start.elapsed()) {
+            if let Some(BackendEvent::Discovered(advertisement)) = self.process(left)? {
+                let stop = done(&advertisement);
+                found.push(advertisement);
+                if stop {
+                    break;
+                }
+            }
+        }
+
+        task::block_on(self.client.stop_discovery(self.controller, address_types)).map_err(mgmt)?;
+        Ok(found)
+    }
+
+    fn known_devices(&mut self) -> Result<Vec<Advertisement>, Error> {
+        Ok(self
+            .devices
+            .values()
+            .map(|(_, _, advertisement)| advertisement.clone())
+            .collect())
+    }
+
+    fn connect(&mut self, _device: &str) -> Result<(), Error> {
+        Err(not_supported("Connecting"))
+    }
+
+    fn discover_services(&mut self, _device: &str) -> Result<Vec<GattService>, Error> {
+        Err(not_supported("GATT"))
+    }
+
+    fn subscribe(
+        &mut self,
+        _device: &str,
+        _service: BleUuid,
+        _characteristic: BleUuid,
+    ) -> Result<(), Error> {
+        Err(not_supported("GATT"))
+    }
+
+    fn read_characteristic(
+        &mut self,
+        _device: &str,
+        _service: BleUuid,
+        _characteristic: BleUuid,
+    ) -> Result<Vec<u8>, Error> {
+        Err(not_supported("GATT"))
+    }
+
+    fn write_characteristic(
+        &mut self,
+        _device: &str,
+        _service: BleUuid,
+        _characteristic: BleUuid,
+        _value: &[u8],
+    ) -> Result<(), Error> {
+        Err(not_supported("GATT"))
+    }
+
+    fn disconnect(&mut self, device: &str) -> Result<(), Error> {
+        let (address, address_type) = self.device(device)?;
+        task::block_on(
+            self.client
+                .disconnect(self.controller, address, address_type),
+        )
+        .map_err(mgmt)?;
+        Ok(())
+    }
+
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<BackendEvent>, Error> {
+        let start = Instant::now();
+        while let Some(left) = timeout.checked_sub(start.elapsed()) {
+            if let Some(event) = self.process(left)? {
+                return Ok(Some(event));
+            }
+        }
+        Ok(None)
+    }
+}
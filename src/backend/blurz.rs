@@ -0,0 +1,534 @@
+//! BlueZ over D-Bus, through blurz.
+
+use super::{
+    device_address, device_path, Advertisement, BackendEvent, BleBackend, GattCharacteristic,
+    GattService, ScanFilter,
+};
+use crate::battery::{BATTERY_LEVEL_UUID, BATTERY_SERVICE_UUID};
+use crate::ble_uuid::BleUuid;
+use crate::error::{dbus, Error};
+use crate::logging::{DISCOVERY, EVENTS, GATT};
+use crate::notify::{poll_readable, NotifySocket, NotifyStats};
+use crate::pairing::{pair_and_trust, PAIR_TIMEOUT};
+use blurz::bluetooth_adapter::BluetoothAdapter;
+use blurz::bluetooth_device::BluetoothDevice;
+use blurz::bluetooth_discovery_session::BluetoothDiscoverySession;
+use blurz::bluetooth_event::BluetoothEvent;
+use blurz::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use blurz::bluetooth_gatt_service::BluetoothGATTService;
+use blurz::bluetooth_session::BluetoothSession;
+use dbus::arg::{cast, RefArg, Variant};
+use dbus::{Message, Props};
+use log::{debug, info, warn};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// BlueZ interface of the battery level of a device, exported instead of
+/// the Battery Service
+const BATTERY_INTERFACE: &str = "org.bluez.Battery1";
+
+/// Changed properties of a PropertiesChanged signal
+type Properties = HashMap<String, Variant<Box<dyn RefArg>>>;
+
+/// The ServicesResolved property of the device at object path `device`
+pub fn services_resolved(session: &BluetoothSession, device: &str) -> Result<bool, Error> {
+    // blurz 没有 ServicesResolved 属性的接口, 直接读 D-Bus 属性
+    let props = Props::new(
+        session.get_connection(),
+        "org.bluez",
+        device,
+        "org.bluez.Device1",
+        1000,
+    );
+    props
+        .get("ServicesResolved")
+        .map_err(|e| Error::Dbus(e.to_string()))?
+        .inner::<bool>()
+        .map_err(|_| Error::Dbus(format!("Invalid ServicesResolved of {}", device)))
+}
+
+/// Call SetDiscoveryFilter on the adapter at object path `adapter`, for the
+/// discovery sessions started afterwards by this D-Bus connection
+pub fn set_discovery_filter(
+    session: &BluetoothSession,
+    adapter: &str,
+    filter: &ScanFilter,
+) -> Result<(), Error> {
+    // blurz 的 set_discovery_filter 不支持 Transport 和 DuplicateData,
+    // UUID 列表为空时还会 panic, 自己拼 a{sv}
+    let mut args: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+    if filter.le_only {
+        args.insert("Transport", Variant(Box::new("le".to_string())));
+    }
+    let uuids = filter
+        .services
+        .iter()
+        .map(|uuid| uuid.to_string())
+        .collect::<Vec<_>>();
+    args.insert("UUIDs", Variant(Box::new(uuids)));
+    if let Some(rssi) = filter.rssi {
+        args.insert("RSSI", Variant(Box::new(rssi)));
+    }
+    if let Some(pathloss) = filter.pathloss {
+        args.insert("Pathloss", Variant(Box::new(pathloss)));
+    }
+    args.insert("DuplicateData", Variant(Box::new(filter.duplicate_data)));
+
+    let message = Message::new_method_call(
+        "org.bluez",
+        adapter,
+        "org.bluez.Adapter1",
+        "SetDiscoveryFilter",
+    )
+    .map_err(Error::Dbus)?
+    .append1(args);
+    session
+        .get_connection()
+        .send_with_reply_and_block(message, 1000)
+        .map_err(|e| Error::Dbus(e.to_string()))?;
+    debug!(target: DISCOVERY, "Discovery filter: {:?}", filter);
+    Ok(())
+}
+
+pub struct BlurzBackend {
+    session: BluetoothSession,
+    adapter: String,
+    scan_filter: Option<ScanFilter>,
+    /// AcquireNotify sockets by device id and characteristic
+    sockets: Vec<(String, BleUuid, NotifySocket)>,
+    /// UUIDs of the characteristics subscribed through Value signals, by
+    /// object path
+    notifying: HashMap<String, BleUuid>,
+    /// Devices whose battery level comes from `org.bluez.Battery1`
+    battery: HashSet<String>,
+    /// Events that arrived while pairing or scanning, returned by
+    /// `next_event` first
+    pending: VecDeque<BackendEvent>,
+    acquired_stats: NotifyStats,
+    signal_stats: NotifyStats,
+}
+
+impl BlurzBackend {
+    /// Use the adapter named `adapter`, e.g. "hci1", or the first one
+    pub fn new(adapter: Option<&str>) -> Result<BlurzBackend, Error> {
+        let session = BluetoothSession::create_session(None).map_err(dbus)?;
+        let adapter = match adapter {
+            Some(name) => {
+                BluetoothAdapter::create_adapter(&session, format!("/org/bluez/{}", name))
+                    .map_err(dbus)?
+                    .get_id()
+            }
+            None => BluetoothAdapter::init(&session).map_err(dbus)?.get_id(),
+        };
+        Ok(BlurzBackend {
+            session,
+            adapter,
+            scan_filter: None,
+            sockets: vec![],
+            notifying: HashMap::new(),
+            battery: HashSet::new(),
+            pending: VecDeque::new(),
+            acquired_stats: NotifyStats::default(),
+            signal_stats: NotifyStats::default(),
+        })
+    }
+
+    pub fn session(&self) -> &BluetoothSession {
+        &self.session
+    }
+
+    /// Object path of the adapter, e.g. "/org/bluez/hci0"
+    pub fn adapter(&self) -> &str {
+        &self.adapter
+    }
+
+    fn advertisement(&self, object_path: &str, rssi: Option<i16>) -> Advertisement {
+        let device = BluetoothDevice::new(&self.session, object_path.to_string());
+        Advertisement {
+            id: object_path.to_string(),
+            address: device
+                .get_address()
+                .unwrap_or_else(|_| device_address(object_path)),
+            name: device.get_name().ok(),
+            rssi: rssi.or_else(|| device.get_rssi().ok()),
+            services: device
+                .get_uuids()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|uuid| uuid.parse().ok())
+                .collect(),
+            paired: device.is_paired().unwrap_or(false),
+            trusted: device.is_trusted().unwrap_or(false),
+        }
+    }
+
+    fn characteristic(
+        &self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<BluetoothGATTCharacteristic<'_>, Error> {
+        let object_path = device;
+        let device = BluetoothDevice::new(&self.session, device.to_string());
+        let mut service_found = false;
+        for service_path in device.get_gatt_services().map_err(dbus)? {
+            let s = BluetoothGATTService::new(&self.session, service_path);
+            if s.get_uuid().map_err(dbus)?.parse::<BleUuid>()? != service {
+                continue;
+            }
+            service_found = true;
+            for characteristic_path in s.get_gatt_characteristics().map_err(dbus)? {
+                let c = BluetoothGATTCharacteristic::new(&self.session, characteristic_path);
+                if c.get_uuid().map_err(dbus)?.parse::<BleUuid>()? == characteristic {
+                    return Ok(c);
+                }
+            }
+        }
+        if !service_found {
+            return Err(Error::ServiceNotFound {
+                device: object_path.to_string(),
+                service,
+            });
+        }
+        Err(Error::CharacteristicNotFound {
+            device: object_path.to_string(),
+            service,
+            characteristic,
+        })
+    }
+
+    fn event(&self, event: BluetoothEvent) -> Option<BackendEvent> {
+        let event = match event {
+            BluetoothEvent::RSSI { object_path, rssi } => {
+                BackendEvent::Discovered(self.advertisement(&object_path, Some(rssi)))
+            }
+            BluetoothEvent::Connected {
+                object_path,
+                connected,
+            } => BackendEvent::Connected {
+                device: object_path,
+                connected,
+            },
+            BluetoothEvent::ServicesResolved {
+                object_path,
+                services_resolved: true,
+            } => BackendEvent::ServicesResolved {
+                device: object_path,
+            },
+            // 只报订阅了的特征值, 读值之类引起的变化不算通知
+            BluetoothEvent::Value { object_path, value } => BackendEvent::Notification {
+                characteristic: Some(*self.notifying.get(&object_path)?),
+                device: device_path(&object_path).to_string(),
+                value: value.to_vec(),
+            },
+            _ => return None,
+        };
+        Some(event)
+    }
+
+    /// The event of a D-Bus signal, if it is one we report
+    fn message_event(&self, message: Message) -> Option<BackendEvent> {
+        if let Some(event) = self.battery_event(&message) {
+            return Some(event);
+        }
+        BluetoothEvent::from(message).and_then(|e| self.event(e))
+    }
+
+    /// A change of `org.bluez.Battery1.Percentage` as a Battery Level
+    /// notification, for the devices subscribed to it
+    fn battery_event(&self, message: &Message) -> Option<BackendEvent> {
+        let (interface, properties): (&str, Properties) = message.read2().ok()?;
+        if interface != BATTERY_INTERFACE {
+            return None;
+        }
+        let device = message.path()?.to_string();
+        if !self.battery.contains(&device) {
+            return None;
+        }
+        let percentage = cast::<u8>(&properties.get("Percentage")?.0)?;
+        Some(BackendEvent::Notification {
+            device,
+            characteristic: Some(BATTERY_LEVEL_UUID),
+            value: vec![*percentage],
+        })
+    }
+
+    /// `org.bluez.Battery1.Percentage` of the device at object path `device`
+    fn battery_percentage(&self, device: &str) -> Result<u8, Error> {
+        Props::new(
+            self.session.get_connection(),
+            "org.bluez",
+            device,
+            BATTERY_INTERFACE,
+            1000,
+        )
+        .get("Percentage")
+        .map_err(|e| Error::Dbus(e.to_string()))?
+        .inner::<u8>()
+        .map_err(|_| Error::Dbus(format!("Invalid battery percentage of {}", device)))
+    }
+
+    /// The Battery Level of a device whose battery service BlueZ keeps to
+    /// itself, `None` for every other characteristic
+    fn battery_fallback(
+        &self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Option<u8> {
+        if service != BATTERY_SERVICE_UUID || characteristic != BATTERY_LEVEL_UUID {
+            return None;
+        }
+        self.battery_percentage(device).ok()
+    }
+}
+
+impl BleBackend for BlurzBackend {
+    fn scan(&mut self, timeout: Duration) -> Result<Vec<Advertisement>, Error> {
+        self.scan_until(timeout, &mut |_| false)
+    }
+
+    fn scan_until(
+        &mut self,
+        timeout: Duration,
+        done: &mut dyn FnMut(&Advertisement) -> bool,
+    ) -> Result<Vec<Advertisement>, Error> {
+        let discover_session =
+            BluetoothDiscoverySession::create_session(&self.session, self.adapter.clone())
+                .map_err(dbus)?;
+        if let Some(filter) = &self.scan_filter {
+            set_discovery_filter(&self.session, &self.adapter, filter)?;
+        }
+        discover_session.start_discovery().map_err(dbus)?;
+
+        let start = Instant::now();
+        let mut found = vec![];
+        while let Some(left) = timeout.checked_sub(start.elapsed()) {
+            let left = left.as_millis() as u32;
+            match self
+                .session
+                .incoming(left)
+                .next()
+                .and_then(|message| self.message_event(message))
+            {
+                Some(BackendEvent::Discovered(advertisement)) => {
+                    let stop = done(&advertisement);
+                    found.push(advertisement);
+                    if stop {
+                        break;
+                    }
+                }
+                Some(event) => self.pending.push_back(event),
+                None => {}
+            }
+        }
+
+        discover_session.stop_discovery().map_err(dbus)?;
+        Ok(found)
+    }
+
+    fn known_devices(&mut self) -> Result<Vec<Advertisement>, Error> {
+        let adapter =
+            BluetoothAdapter::create_adapter(&self.session, self.adapter.clone()).map_err(dbus)?;
+        Ok(adapter
+            .get_device_list()
+            .map_err(dbus)?
+            .iter()
+            .map(|path| self.advertisement(path, None))
+            .collect())
+    }
+
+    fn set_scan_filter(&mut self, filter: ScanFilter) -> Result<(), Error> {
+        self.scan_filter = Some(filter);
+        Ok(())
+    }
+
+    fn pair(&mut self, device: &str) -> Result<(), Error> {
+        // 配对时其他手柄还在用, 它们的信号先存起来, 断线也不能丢
+        let mut messages = vec![];
+        let state = pair_and_trust(&self.session, device, PAIR_TIMEOUT, &mut |message| {
+            messages.push(message)
+        });
+        for message in messages {
+            if let Some(event) = self.message_event(message) {
+                self.pending.push_back(event);
+            }
+        }
+        info!(target: DISCOVERY, "{}: {}", device, state?);
+        Ok(())
+    }
+
+    fn connect(&mut self, device: &str) -> Result<(), Error> {
+        BluetoothDevice::new(&self.session, device.to_string())
+            .connect(10000)
+            .map_err(dbus)
+    }
+
+    fn services_resolved(&mut self, device: &str) -> Result<bool, Error> {
+        services_resolved(&self.session, device)
+    }
+
+    fn discover_services(&mut self, device: &str) -> Result<Vec<GattService>, Error> {
+        let device = BluetoothDevice::new(&self.session, device.to_string());
+        let mut services = vec![];
+        for service_path in device.get_gatt_services().map_err(dbus)? {
+            let service = BluetoothGATTService::new(&self.session, service_path);
+            let mut characteristics = vec![];
+            for characteristic_path in service.get_gatt_characteristics().map_err(dbus)? {
+                let c = BluetoothGATTCharacteristic::new(&self.session, characteristic_path);
+                characteristics.push(GattCharacteristic {
+                    uuid: c.get_uuid().map_err(dbus)?.parse()?,
+                    flags: c.get_flags().map_err(dbus)?,
+                });
+            }
+            services.push(GattService {
+                uuid: Some(service.get_uuid().map_err(dbus)?.parse()?),
+                characteristics,
+            });
+        }
+        Ok(services)
+    }
+
+    fn subscribe(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<(), Error> {
+        let c = match self.characteristic(device, service, characteristic) {
+            Ok(c) => c,
+            // BlueZ 的 battery 插件占用了 180f, 不导出 GATT, 改听 Battery1 的属性变化
+            Err(e) => match self.battery_fallback(device, service, characteristic) {
+                Some(_) => {
+                    debug!(target: GATT, "{}: battery level from {}", device, BATTERY_INTERFACE);
+                    self.battery.insert(device.to_string());
+                    return Ok(());
+                }
+                None => return Err(e),
+            },
+        };
+        // 优先用 AcquireNotify 的 socket, 不经过 D-Bus 信号
+        let acquired = c.acquire_notify();
+        if let Err(e) = &acquired {
+            warn!(
+                target: GATT,
+                "AcquireNotify failed on {}, using Value signals: {}",
+                device, e
+            );
+            c.start_notify().map_err(dbus)?;
+            self.notifying.insert(c.get_id(), characteristic);
+        }
+
+        if let Ok((fd, mtu)) = acquired {
+            let socket = unsafe { NotifySocket::from_raw_fd(fd.into_fd(), mtu) };
+            self.sockets
+                .retain(|(d, c, _)| d != device || *c != characteristic);
+            self.sockets
+                .push((device.to_string(), characteristic, socket));
+        }
+        Ok(())
+    }
+
+    fn read_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<Vec<u8>, Error> {
+        match self.characteristic(device, service, characteristic) {
+            Ok(c) => c.read_value(None).map_err(dbus),
+            Err(e) => match self.battery_fallback(device, service, characteristic) {
+                Some(percentage) => Ok(vec![percentage]),
+                None => Err(e),
+            },
+        }
+    }
+
+    fn write_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.characteristic(device, service, characteristic)?
+            .write_value(value.to_vec(), None)
+            .map_err(dbus)
+    }
+
+    fn disconnect(&mut self, device: &str) -> Result<(), Error> {
+        BluetoothDevice::new(&self.session, device.to_string())
+            .disconnect()
+            .map_err(dbus)
+    }
+
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<BackendEvent>, Error> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+        let start = Instant::now();
+        let mut readable_at = start;
+        loop {
+            // 连接断了 poll 会一直返回可读, 不能当作没有消息
+            if !self.session.get_connection().is_connected() {
+                return Err(Error::Closed("D-Bus connection".to_string()));
+            }
+            // 先处理 D-Bus 已经收到的消息
+            while let Some(message) = self.session.incoming(0).next() {
+                if let Some(event) = self.message_event(message) {
+                    if let BackendEvent::Notification { value, .. } = &event {
+                        self.signal_stats.record(value.len(), readable_at);
+                    }
+                    return Ok(Some(event));
+                }
+            }
+
+            let left = match timeout.checked_sub(start.elapsed()) {
+                Some(left) if !left.is_zero() => left,
+                _ => return Ok(None),
+            };
+            let mut fds = self
+                .sockets
+                .iter()
+                .map(|(_, _, socket)| socket.as_raw_fd())
+                .collect::<Vec<_>>();
+            fds.extend(
+                self.session
+                    .get_connection()
+                    .watch_fds()
+                    .iter()
+                    .filter(|watch| watch.readable())
+                    .map(|watch| watch.fd()),
+            );
+            let ready = poll_readable(&fds, left)?;
+            readable_at = Instant::now();
+
+            if let Some(i) = ready[..self.sockets.len()].iter().position(|r| *r) {
+                let (device, characteristic, socket) = &mut self.sockets[i];
+                match socket.read_packet() {
+                    Ok(value) => {
+                        self.acquired_stats.record(value.len(), readable_at);
+                        return Ok(Some(BackendEvent::Notification {
+                            device: device.clone(),
+                            characteristic: Some(*characteristic),
+                            value,
+                        }));
+                    }
+                    Err(e) => {
+                        // 断开连接时 BlueZ 会关掉 socket
+                        info!(target: EVENTS, "Notify socket of {} closed: {}", device, e);
+                        self.sockets.remove(i);
+                    }
+                }
+            }
+        }
+    }
+
+    fn notify_stats(&self) -> Vec<(&'static str, NotifyStats)> {
+        vec![
+            ("AcquireNotify", self.acquired_stats.clone()),
+            ("PropertiesChanged", self.signal_stats.clone()),
+        ]
+    }
+}
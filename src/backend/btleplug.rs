@@ -0,0 +1,276 @@
+//! BlueZ HCI socket, through btleplug.
+
+use super::{
+    property_flags, Advertisement, BackendEvent, BleBackend, GattCharacteristic, GattService,
+};
+use crate::ble_uuid::BleUuid;
+use crate::error::Error;
+use btleplug::api::{Central, CentralEvent, Characteristic, Peripheral, UUID};
+use btleplug::bluez::adapter::ConnectedAdapter;
+use btleplug::bluez::manager::Manager;
+use std::collections::{HashSet, VecDeque};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn err(e: btleplug::Error) -> Error {
+    Error::Backend(e.to_string())
+}
+
+fn ble_uuid(uuid: UUID) -> BleUuid {
+    match uuid {
+        UUID::B16(short) => BleUuid::from_u16(short),
+        UUID::B128(bytes) => BleUuid::from_le_bytes(bytes),
+    }
+}
+
+fn advertisement<P: Peripheral>(peripheral: &P) -> Advertisement {
+    let address = peripheral.address().to_string();
+    Advertisement {
+        id: address.clone(),
+        address,
+        name: peripheral.properties().local_name,
+        rssi: None,
+        services: vec![],
+        paired: false,
+        trusted: false,
+    }
+}
+
+pub struct BtleplugBackend {
+    central: ConnectedAdapter,
+    events: Receiver<BackendEvent>,
+    sender: Sender<BackendEvent>,
+    /// Devices whose notifications are already forwarded
+    listening: HashSet<String>,
+    /// Events read while scanning, returned by `next_event` first
+    pending: VecDeque<BackendEvent>,
+}
+
+impl BtleplugBackend {
+    /// Use the adapter named `adapter`, e.g. "hci1", or the first one
+    pub fn new(adapter: Option<&str>) -> Result<BtleplugBackend, Error> {
+        let manager = Manager::new().map_err(err)?;
+        let adapter = manager
+            .adapters()
+            .map_err(err)?
+            .into_iter()
+            .find(|a| adapter.is_none_or(|name| a.name == name))
+            .ok_or_else(|| Error::AdapterNotFound(adapter.unwrap_or("default").to_string()))?;
+        let central = adapter.connect().map_err(err)?;
+
+        let (sender, events) = channel();
+        if let Some(receiver) = central.event_receiver() {
+            let central = central.clone();
+            let sender = sender.clone();
+            thread::spawn(move || {
+                for event in receiver.iter() {
+                    let event = match event {
+                        // 名字常常在扫描响应里才有, 更新也当作发现
+                        CentralEvent::DeviceDiscovered(address)
+                        | CentralEvent::DeviceUpdated(address) => {
+                            match central.peripheral(address) {
+                                Some(peripheral) => {
+                                    BackendEvent::Discovered(advertisement(&peripheral))
+                                }
+                                None => continue,
+                            }
+                        }
+                        CentralEvent::DeviceConnected(address) => BackendEvent::Connected {
+                            device: address.to_string(),
+                            connected: true,
+                        },
+                        CentralEvent::DeviceDisconnected(address) => BackendEvent::Connected {
+                            device: address.to_string(),
+                            connected: false,
+                        },
+                        _ => continue,
+                    };
+                    if sender.send(event).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+
+        Ok(BtleplugBackend {
+            central,
+            events,
+            sender,
+            listening: HashSet::new(),
+            pending: VecDeque::new(),
+        })
+    }
+
+    fn characteristic(
+        &self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<Characteristic, Error> {
+        let peripheral = self.peripheral(device)?;
+        let mut characteristics = peripheral.characteristics();
+        if characteristics.is_empty() {
+            characteristics = peripheral
+                .discover_characteristics()
+                .map_err(err)?
+                .into_iter()
+                .collect();
+        }
+        characteristics
+            .into_iter()
+            .find(|c| ble_uuid(c.uuid) == characteristic)
+            .ok_or_else(|| Error::CharacteristicNotFound {
+                device: device.to_string(),
+                service,
+                characteristic,
+            })
+    }
+
+    fn peripheral(&self, device: &str) -> Result<impl Peripheral, Error> {
+        self.central
+            .peripherals()
+            .into_iter()
+            .find(|p| p.address().to_string() == device)
+            .ok_or_else(|| Error::DeviceNotFound(device.to_string()))
+    }
+}
+
+impl BleBackend for BtleplugBackend {
+    fn scan(&mut self, timeout: Duration) -> Result<Vec<Advertisement>, Error> {
+        self.central.start_scan().map_err(err)?;
+        thread::sleep(timeout);
+        self.central.stop_scan().map_err(err)?;
+        self.known_devices()
+    }
+
+    fn scan_until(
+        &mut self,
+        timeout: Duration,
+        done: &mut dyn FnMut(&Advertisement) -> bool,
+    ) -> Result<Vec<Advertisement>, Error> {
+        self.central.start_scan().map_err(err)?;
+        let start = Instant::now();
+        let mut found = vec![];
+        while let Some(left) = timeout.checked_sub(start.elapsed()) {
+            match self.events.recv_timeout(left) {
+                Ok(BackendEvent::Discovered(advertisement)) => {
+                    let stop = done(&advertisement);
+                    found.push(advertisement);
+                    if stop {
+                        break;
+                    }
+                }
+                // 扫描期间其他设备的事件留给 next_event
+                Ok(event) => self.pending.push_back(event),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.central.stop_scan().ok();
+                    return Err(Error::Closed("Event channel".to_string()));
+                }
+            }
+        }
+        self.central.stop_scan().map_err(err)?;
+        Ok(found)
+    }
+
+    fn known_devices(&mut self) -> Result<Vec<Advertisement>, Error> {
+        Ok(self
+            .central
+            .peripherals()
+            .iter()
+            .map(advertisement)
+            .collect())
+    }
+
+    fn connect(&mut self, device: &str) -> Result<(), Error> {
+        let peripheral = self.peripheral(device)?;
+        if !peripheral.is_connected() {
+            peripheral.connect().map_err(err)?;
+        }
+
+        // 通知的回调只能在连接之后注册
+        if self.listening.insert(device.to_string()) {
+            let sender = self.sender.clone();
+            let device = device.to_string();
+            peripheral.on_notification(Box::new(move |notification| {
+                sender
+                    .send(BackendEvent::Notification {
+                        device: device.clone(),
+                        characteristic: Some(ble_uuid(notification.uuid)),
+                        value: notification.value,
+                    })
+                    .ok();
+            }));
+        }
+        Ok(())
+    }
+
+    fn services_resolved(&mut self, device: &str) -> Result<bool, Error> {
+        // connect 返回时服务已经发现好了
+        Ok(self.peripheral(device)?.is_connected())
+    }
+
+    fn discover_services(&mut self, device: &str) -> Result<Vec<GattService>, Error> {
+        let characteristics = self
+            .peripheral(device)?
+            .discover_characteristics()
+            .map_err(err)?
+            .into_iter()
+            .map(|c| GattCharacteristic {
+                uuid: ble_uuid(c.uuid),
+                flags: property_flags(c.properties.bits()),
+            })
+            .collect();
+        Ok(vec![GattService {
+            uuid: None,
+            characteristics,
+        }])
+    }
+
+    fn subscribe(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<(), Error> {
+        let c = self.characteristic(device, service, characteristic)?;
+        self.peripheral(device)?.subscribe(&c).map_err(err)
+    }
+
+    fn read_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<Vec<u8>, Error> {
+        let c = self.characteristic(device, service, characteristic)?;
+        self.peripheral(device)?.read(&c).map_err(err)
+    }
+
+    fn write_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let c = self.characteristic(device, service, characteristic)?;
+        self.peripheral(device)?.command(&c, value).map_err(err)
+    }
+
+    fn disconnect(&mut self, device: &str) -> Result<(), Error> {
+        self.peripheral(device)?.disconnect().map_err(err)
+    }
+
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<BackendEvent>, Error> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(Some(event));
+        }
+        match self.events.recv_timeout(timeout) {
+            Ok(event) => Ok(Some(event)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Closed("Event channel".to_string())),
+        }
+    }
+}
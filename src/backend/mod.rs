@@ -0,0 +1,376 @@
+//! One interface over the BLE stacks, each implementation behind a cargo
+//! feature of the same name.
+
+use crate::ble_uuid::BleUuid;
+use crate::error::Error;
+#[cfg(unix)]
+use crate::notify::NotifyStats;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "bluez")]
+pub mod bluez;
+#[cfg(feature = "blurz")]
+pub mod blurz;
+#[cfg(feature = "btleplug")]
+pub mod btleplug;
+#[cfg(feature = "rumble")]
+pub mod rumble;
+pub mod sim;
+
+/// Strips the service/characteristic part from a BlueZ object path, e.g.
+/// "/org/bluez/hci0/dev_00_81_F9_DF_B0_40/service000c/char000d" becomes
+/// "/org/bluez/hci0/dev_00_81_F9_DF_B0_40"
+pub fn device_path(object_path: &str) -> &str {
+    match object_path.find("/dev_") {
+        Some(start) => match object_path[start + 1..].find('/') {
+            Some(end) => &object_path[..start + 1 + end],
+            None => object_path,
+        },
+        None => object_path,
+    }
+}
+
+/// MAC address of a device id, which is either a BlueZ object path or the
+/// address itself, e.g. "/org/bluez/hci0/dev_00_81_F9_DF_B0_40" becomes
+/// "00:81:F9:DF:B0:40"
+pub fn device_address(id: &str) -> String {
+    let path = device_path(id);
+    match path.find("/dev_") {
+        Some(start) => path[start + 5..].replace('_', ":"),
+        None => id.to_string(),
+    }
+}
+
+/// Names of the characteristic property bits, as BlueZ reports them
+pub fn property_flags(bits: u8) -> Vec<String> {
+    const NAMES: [&str; 8] = [
+        "broadcast",
+        "read",
+        "write-without-response",
+        "write",
+        "notify",
+        "indicate",
+        "authenticated-signed-writes",
+        "extended-properties",
+    ];
+    NAMES
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bits & (1 << i) != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// A device seen while scanning or already known to the adapter
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Advertisement {
+    /// Backend specific id, the object path for BlueZ over D-Bus and the
+    /// address for the HCI socket backends
+    pub id: String,
+    pub address: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+    /// Advertised services, empty when the backend does not report them
+    pub services: Vec<BleUuid>,
+    /// Bonding state, always false for backends that do not report it
+    pub paired: bool,
+    pub trusted: bool,
+}
+
+/// What a scan reports, for the stacks that can filter before the devices
+/// reach us. `DeviceFilter` still picks the devices afterwards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanFilter {
+    /// Only LE devices, no BR/EDR inquiry
+    pub le_only: bool,
+    /// Devices advertising any of these services, all when empty
+    pub services: Vec<BleUuid>,
+    /// Weakest signal in dBm, not together with `pathloss`
+    pub rssi: Option<i16>,
+    /// Largest path loss in dB, for devices advertising their Tx power
+    pub pathloss: Option<u16>,
+    /// Report every advertisement instead of only the changed ones
+    pub duplicate_data: bool,
+}
+
+impl Default for ScanFilter {
+    fn default() -> ScanFilter {
+        ScanFilter {
+            le_only: true,
+            services: vec![],
+            rssi: None,
+            pathloss: None,
+            // RSSI 的变化要靠重复的广播包才会上报
+            duplicate_data: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GattCharacteristic {
+    pub uuid: BleUuid,
+    pub flags: Vec<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GattService {
+    /// `None` when the backend does not report services
+    pub uuid: Option<BleUuid>,
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BackendEvent {
+    Discovered(Advertisement),
+    Connected {
+        device: String,
+        connected: bool,
+    },
+    ServicesResolved {
+        device: String,
+    },
+    /// `characteristic` is `None` when the backend does not report it
+    Notification {
+        device: String,
+        characteristic: Option<BleUuid>,
+        value: Vec<u8>,
+    },
+}
+
+/// Scan, connect and talk GATT to a device.
+///
+/// Devices are named by the `id` of their `Advertisement`, services and
+/// characteristics by their UUID.
+pub trait BleBackend {
+    /// Scan for `timeout`, returning the devices seen
+    fn scan(&mut self, timeout: Duration) -> Result<Vec<Advertisement>, Error>;
+
+    /// Scan for `timeout` at most, calling `done` with every device seen
+    /// and stopping as soon as it returns true. Stacks that only report
+    /// the devices after scanning call it once the scan is over.
+    fn scan_until(
+        &mut self,
+        timeout: Duration,
+        done: &mut dyn FnMut(&Advertisement) -> bool,
+    ) -> Result<Vec<Advertisement>, Error> {
+        let mut found = self.scan(timeout)?;
+        if let Some(last) = found.iter().position(done) {
+            found.truncate(last + 1);
+        }
+        Ok(found)
+    }
+
+    /// Devices the adapter already knows about, paired or seen before
+    fn known_devices(&mut self) -> Result<Vec<Advertisement>, Error>;
+
+    /// Narrow down the following scans, stacks that cannot filter ignore it
+    fn set_scan_filter(&mut self, _filter: ScanFilter) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Pair with the device and trust it if the stack supports it and it
+    /// isn't already
+    fn pair(&mut self, _device: &str) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn connect(&mut self, device: &str) -> Result<(), Error>;
+
+    /// Whether the services of a connected device are resolved, so GATT
+    /// calls can be made. Stacks that resolve inside `connect` say so
+    /// once connected.
+    fn services_resolved(&mut self, _device: &str) -> Result<bool, Error> {
+        Ok(false)
+    }
+
+    fn discover_services(&mut self, device: &str) -> Result<Vec<GattService>, Error>;
+
+    /// Enable notifications, the values arrive through `next_event`
+    fn subscribe(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<(), Error>;
+
+    fn read_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<Vec<u8>, Error>;
+
+    fn write_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+        value: &[u8],
+    ) -> Result<(), Error>;
+
+    fn disconnect(&mut self, device: &str) -> Result<(), Error>;
+
+    /// Wait up to `timeout` for the next event, `None` on timeout
+    fn next_event(&mut self, timeout: Duration) -> Result<Option<BackendEvent>, Error>;
+
+    /// Counters of each notification path the backend uses, by name
+    #[cfg(unix)]
+    fn notify_stats(&self) -> Vec<(&'static str, NotifyStats)> {
+        vec![]
+    }
+}
+
+/// The backend of the first enabled feature, in the order blurz, btleplug,
+/// rumble, bluez. `adapter` is the adapter name, e.g. "hci1", the first one
+/// if `None`.
+pub fn default_backend(adapter: Option<&str>) -> Result<Box<dyn BleBackend>, Error> {
+    #[cfg(feature = "blurz")]
+    return Ok(Box::new(self::blurz::BlurzBackend::new(adapter)?));
+    #[cfg(all(not(feature = "blurz"), feature = "btleplug"))]
+    return Ok(Box::new(self::btleplug::BtleplugBackend::new(adapter)?));
+    #[cfg(all(not(feature = "blurz"), not(feature = "btleplug"), feature = "rumble"))]
+    return Ok(Box::new(self::rumble::RumbleBackend::new(adapter)?));
+    #[cfg(all(
+        not(feature = "blurz"),
+        not(feature = "btleplug"),
+        not(feature = "rumble"),
+        feature = "bluez"
+    ))]
+    return Ok(Box::new(self::bluez::BluezBackend::new(adapter)?));
+    #[allow(unreachable_code)]
+    let _ = adapter;
+    #[allow(unreachable_code)]
+    Err(Error::Backend("No BLE backend enabled".to_string()))
+}
+
+/// How long `connect_and_resolve` waits by default
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connect and wait up to `timeout` for the services to be resolved,
+/// instead of sleeping before the first GATT call. Events of other devices
+/// arriving meanwhile are returned for the caller to handle.
+pub fn connect_and_resolve<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    device: &str,
+    timeout: Duration,
+) -> Result<Vec<BackendEvent>, Error> {
+    backend.connect(device)?;
+    wait_services_resolved(backend, device, timeout)
+}
+
+/// The waiting half of `connect_and_resolve`, for a device that is already
+/// connecting
+pub fn wait_services_resolved<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    device: &str,
+    timeout: Duration,
+) -> Result<Vec<BackendEvent>, Error> {
+    let mut others = vec![];
+    // 已经连接过的设备不会再发 ServicesResolved 事件
+    if backend.services_resolved(device)? {
+        return Ok(others);
+    }
+
+    let start = Instant::now();
+    loop {
+        let left = match timeout.checked_sub(start.elapsed()) {
+            Some(left) if !left.is_zero() => left,
+            _ => {
+                return Err(Error::Timeout {
+                    device: device.to_string(),
+                    operation: "resolving services",
+                    timeout,
+                })
+            }
+        };
+        match backend.next_event(left)? {
+            Some(BackendEvent::ServicesResolved { device: d }) if d == device => return Ok(others),
+            Some(BackendEvent::Connected {
+                device: d,
+                connected: false,
+            }) if d == device => return Err(Error::Disconnected(device.to_string())),
+            Some(event) => others.push(event),
+            None => {}
+        }
+    }
+}
+
+/// Find a characteristic by the UUIDs of its service and itself. A service
+/// without UUID matches any service.
+pub fn find_characteristic(
+    services: &[GattService],
+    service: BleUuid,
+    characteristic: BleUuid,
+) -> Option<&GattCharacteristic> {
+    services
+        .iter()
+        .filter(|s| s.uuid.is_none_or(|uuid| uuid == service))
+        .flat_map(|s| s.characteristics.iter())
+        .find(|c| c.uuid == characteristic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MMC_SERVICE: BleUuid = BleUuid::from_u16(0x1809);
+    const MMC_CHAR: BleUuid = BleUuid::from_u16(0x2a1e);
+
+    #[test]
+    fn flags_and_addresses() {
+        assert_eq!(property_flags(0x12), vec!["read", "notify"]);
+
+        assert_eq!(
+            device_address("/org/bluez/hci0/dev_00_81_F9_DF_B0_40/service000c/char000d"),
+            "00:81:F9:DF:B0:40"
+        );
+        assert_eq!(device_address("00:81:F9:DF:B0:40"), "00:81:F9:DF:B0:40");
+    }
+
+    #[test]
+    fn find_characteristic_by_uuid() {
+        let mut services = vec![GattService {
+            uuid: Some(MMC_SERVICE),
+            characteristics: vec![GattCharacteristic {
+                uuid: MMC_CHAR,
+                flags: vec![],
+            }],
+        }];
+        let bell_service = BleUuid::from_u16(0x8850);
+
+        assert!(find_characteristic(&services, MMC_SERVICE, MMC_CHAR).is_some());
+        assert!(find_characteristic(&services, bell_service, MMC_CHAR).is_none());
+        services[0].uuid = None;
+        assert!(find_characteristic(&services, bell_service, MMC_CHAR).is_some());
+    }
+
+    #[test]
+    fn connect_waits_for_resolved_services() {
+        let mut sim = sim::SimBackend::new();
+        sim.add_device(
+            sim::SimDevice::new("00:81:F9:DF:B0:40", "MMC").service(MMC_SERVICE, &[MMC_CHAR]),
+        )
+        .add_device(sim::SimDevice::new("E0:7D:EA:00:00:01", "bell").never_resolves());
+
+        let others = connect_and_resolve(&mut sim, "00:81:F9:DF:B0:40", RESOLVE_TIMEOUT).unwrap();
+        assert_eq!(
+            others,
+            vec![BackendEvent::Connected {
+                device: "00:81:F9:DF:B0:40".to_string(),
+                connected: true
+            }]
+        );
+        assert!(sim.services_resolved("00:81:F9:DF:B0:40").unwrap());
+        // 再连一次直接返回
+        assert!(
+            connect_and_resolve(&mut sim, "00:81:F9:DF:B0:40", RESOLVE_TIMEOUT)
+                .unwrap()
+                .is_empty()
+        );
+
+        match connect_and_resolve(&mut sim, "E0:7D:EA:00:00:01", Duration::from_millis(10)) {
+            Err(Error::Timeout { device, .. }) => assert_eq!(device, "E0:7D:EA:00:00:01"),
+            r => panic!("unexpected result {:?}", r),
+        }
+    }
+}
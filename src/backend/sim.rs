@@ -0,0 +1,462 @@
+//! In-memory backend with scripted devices, for tests without an adapter.
+
+use super::{Advertisement, BackendEvent, BleBackend, GattCharacteristic, GattService};
+use crate::ble_uuid::BleUuid;
+use crate::error::Error;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+
+/// A scripted peripheral
+#[derive(Clone, Debug)]
+pub struct SimDevice {
+    advertisement: Advertisement,
+    services: Vec<GattService>,
+    connect_error: Option<String>,
+    never_resolves: bool,
+}
+
+impl SimDevice {
+    /// A device named `name`, its address doubles as the id
+    pub fn new(address: &str, name: &str) -> SimDevice {
+        SimDevice {
+            advertisement: Advertisement {
+                id: address.to_string(),
+                address: address.to_string(),
+                name: Some(name.to_string()),
+                rssi: None,
+                services: vec![],
+                paired: false,
+                trusted: false,
+            },
+            services: vec![],
+            connect_error: None,
+            never_resolves: false,
+        }
+    }
+
+    pub fn rssi(mut self, rssi: i16) -> SimDevice {
+        self.advertisement.rssi = Some(rssi);
+        self
+    }
+
+    /// Already paired, listed by `known_devices` without a scan
+    pub fn paired(mut self) -> SimDevice {
+        self.advertisement.paired = true;
+        self
+    }
+
+    /// Add an advertised service with notify/read/write characteristics
+    pub fn service(mut self, uuid: BleUuid, characteristics: &[BleUuid]) -> SimDevice {
+        self.advertisement.services.push(uuid);
+        self.services.push(GattService {
+            uuid: Some(uuid),
+            characteristics: characteristics
+                .iter()
+                .map(|c| GattCharacteristic {
+                    uuid: *c,
+                    flags: vec!["read".into(), "write".into(), "notify".into()],
+                })
+                .collect(),
+        });
+        self
+    }
+
+    /// Every connect attempt fails with `message`
+    pub fn connect_error(mut self, message: &str) -> SimDevice {
+        self.connect_error = Some(message.to_string());
+        self
+    }
+
+    /// Connecting works but the services are never resolved
+    pub fn never_resolves(mut self) -> SimDevice {
+        self.never_resolves = true;
+        self
+    }
+
+    fn has_characteristic(&self, service: BleUuid, characteristic: BleUuid) -> bool {
+        super::find_characteristic(&self.services, service, characteristic).is_some()
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Notify {
+        device: String,
+        characteristic: BleUuid,
+        value: Vec<u8>,
+    },
+    Disconnect {
+        device: String,
+    },
+}
+
+/// Plays back the scripted devices. Notifications and disconnects are
+/// queued up front and handed out by `next_event` in order, a
+/// notification only reaches the caller if the characteristic is
+/// subscribed at that point.
+#[derive(Debug, Default)]
+pub struct SimBackend {
+    devices: Vec<SimDevice>,
+    steps: VecDeque<Step>,
+    events: VecDeque<BackendEvent>,
+    seen: HashSet<String>,
+    /// Advertisements handed out by scans
+    scanned: usize,
+    connected: HashSet<String>,
+    /// Connected devices whose ServicesResolved event was handed out
+    resolved: HashSet<String>,
+    /// Device id and characteristic
+    subscribed: HashSet<(String, BleUuid)>,
+    values: HashMap<(String, BleUuid), Vec<u8>>,
+}
+
+impl SimBackend {
+    pub fn new() -> SimBackend {
+        SimBackend::default()
+    }
+
+    pub fn add_device(&mut self, device: SimDevice) -> &mut SimBackend {
+        self.devices.push(device);
+        self
+    }
+
+    /// Queue a notification of a characteristic
+    pub fn notify(
+        &mut self,
+        device: &str,
+        characteristic: BleUuid,
+        value: &[u8],
+    ) -> &mut SimBackend {
+        self.steps.push_back(Step::Notify {
+            device: device.to_string(),
+            characteristic,
+            value: value.to_vec(),
+        });
+        self
+    }
+
+    /// Queue the device dropping the connection
+    pub fn drop_connection(&mut self, device: &str) -> &mut SimBackend {
+        self.steps.push_back(Step::Disconnect {
+            device: device.to_string(),
+        });
+        self
+    }
+
+    /// Make connecting to the device fail with `message`, or succeed again
+    pub fn set_connect_error(&mut self, device: &str, message: Option<&str>) -> &mut SimBackend {
+        if let Some(d) = self
+            .devices
+            .iter_mut()
+            .find(|d| d.advertisement.id == device)
+        {
+            d.connect_error = message.map(|m| m.to_string());
+        }
+        self
+    }
+
+    /// How many advertisements the scans so far reported, to check they
+    /// stop early
+    pub fn scanned(&self) -> usize {
+        self.scanned
+    }
+
+    pub fn is_connected(&self, device: &str) -> bool {
+        self.connected.contains(device)
+    }
+
+    pub fn is_subscribed(&self, device: &str, characteristic: BleUuid) -> bool {
+        self.subscribed
+            .contains(&(device.to_string(), characteristic))
+    }
+
+    /// Value a read of the characteristic returns until it is written
+    pub fn set_value(
+        &mut self,
+        device: &str,
+        characteristic: BleUuid,
+        value: &[u8],
+    ) -> &mut SimBackend {
+        self.values
+            .insert((device.to_string(), characteristic), value.to_vec());
+        self
+    }
+
+    /// Last value written to a characteristic
+    pub fn written(&self, device: &str, characteristic: BleUuid) -> Option<&[u8]> {
+        self.values
+            .get(&(device.to_string(), characteristic))
+            .map(|v| v.as_slice())
+    }
+
+    fn device(&self, device: &str) -> Result<&SimDevice, Error> {
+        self.devices
+            .iter()
+            .find(|d| d.advertisement.id == device)
+            .ok_or_else(|| Error::DeviceNotFound(device.to_string()))
+    }
+
+    fn connected_characteristic(
+        &self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<(String, BleUuid), Error> {
+        if !self.is_connected(device) {
+            return Err(Error::NotConnected(device.to_string()));
+        }
+        if !self
+            .device(device)?
+            .has_characteristic(service, characteristic)
+        {
+            return Err(Error::CharacteristicNotFound {
+                device: device.to_string(),
+                service,
+                characteristic,
+            });
+        }
+        Ok((device.to_string(), characteristic))
+    }
+
+    fn drop_device(&mut self, device: &str) {
+        self.connected.remove(device);
+        self.resolved.remove(device);
+        self.subscribed.retain(|(d, _)| d != device);
+    }
+}
+
+impl BleBackend for SimBackend {
+    fn scan(&mut self, timeout: Duration) -> Result<Vec<Advertisement>, Error> {
+        self.scan_until(timeout, &mut |_| false)
+    }
+
+    fn scan_until(
+        &mut self,
+        _timeout: Duration,
+        done: &mut dyn FnMut(&Advertisement) -> bool,
+    ) -> Result<Vec<Advertisement>, Error> {
+        let mut found = vec![];
+        for device in self.devices.iter() {
+            self.seen.insert(device.advertisement.id.clone());
+            self.scanned += 1;
+            found.push(device.advertisement.clone());
+            if done(&device.advertisement) {
+                break;
+            }
+        }
+        Ok(found)
+    }
+
+    fn known_devices(&mut self) -> Result<Vec<Advertisement>, Error> {
+        Ok(self
+            .devices
+            .iter()
+            .filter(|d| d.advertisement.paired || self.seen.contains(&d.advertisement.id))
+            .map(|d| d.advertisement.clone())
+            .collect())
+    }
+
+    fn pair(&mut self, device: &str) -> Result<(), Error> {
+        let d = self
+            .devices
+            .iter_mut()
+            .find(|d| d.advertisement.id == device)
+            .ok_or_else(|| Error::DeviceNotFound(device.to_string()))?;
+        d.advertisement.paired = true;
+        Ok(())
+    }
+
+    fn connect(&mut self, device: &str) -> Result<(), Error> {
+        let d = self.device(device)?;
+        if let Some(message) = &d.connect_error {
+            return Err(Error::Backend(message.clone()));
+        }
+        let resolves = !d.never_resolves;
+        if self.connected.insert(device.to_string()) {
+            self.events.push_back(BackendEvent::Connected {
+                device: device.to_string(),
+                connected: true,
+            });
+            if resolves {
+                self.events.push_back(BackendEvent::ServicesResolved {
+                    device: device.to_string(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn services_resolved(&mut self, device: &str) -> Result<bool, Error> {
+        Ok(self.resolved.contains(device))
+    }
+
+    fn discover_services(&mut self, device: &str) -> Result<Vec<GattService>, Error> {
+        if !self.is_connected(device) {
+            return Err(Error::NotConnected(device.to_string()));
+        }
+        Ok(self.device(device)?.services.clone())
+    }
+
+    fn subscribe(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<(), Error> {
+        let key = self.connected_characteristic(device, service, characteristic)?;
+        self.subscribed.insert(key);
+        Ok(())
+    }
+
+    fn read_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+    ) -> Result<Vec<u8>, Error> {
+        let key = self.connected_characteristic(device, service, characteristic)?;
+        Ok(self.values.get(&key).cloned().unwrap_or_default())
+    }
+
+    fn write_characteristic(
+        &mut self,
+        device: &str,
+        service: BleUuid,
+        characteristic: BleUuid,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        let key = self.connected_characteristic(device, service, characteristic)?;
+        self.values.insert(key, value.to_vec());
+        Ok(())
+    }
+
+    fn disconnect(&mut self, device: &str) -> Result<(), Error> {
+        if self.connected.contains(device) {
+            self.drop_device(device);
+            self.events.push_back(BackendEvent::Connected {
+                device: device.to_string(),
+                connected: false,
+            });
+        }
+        Ok(())
+    }
+
+    fn next_event(&mut self, _timeout: Duration) -> Result<Option<BackendEvent>, Error> {
+        if let Some(event) = self.events.pop_front() {
+            if let BackendEvent::ServicesResolved { device } = &event {
+                if self.connected.contains(device) {
+                    self.resolved.insert(device.clone());
+                }
+            }
+            return Ok(Some(event));
+        }
+
+        while let Some(step) = self.steps.pop_front() {
+            match step {
+                Step::Notify {
+                    device,
+                    characteristic,
+                    value,
+                } => {
+                    let key = (device, characteristic);
+                    if self.subscribed.contains(&key) {
+                        return Ok(Some(BackendEvent::Notification {
+                            device: key.0,
+                            characteristic: Some(key.1),
+                            value,
+                        }));
+                    }
+                }
+                Step::Disconnect { device } => {
+                    if self.connected.contains(&device) {
+                        self.drop_device(&device);
+                        return Ok(Some(BackendEvent::Connected {
+                            device,
+                            connected: false,
+                        }));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MMC: &str = "00:81:F9:DF:B0:40";
+    const SERVICE: BleUuid = BleUuid::from_u16(0x1809);
+    const CHAR: BleUuid = BleUuid::from_u16(0x2a1e);
+
+    fn thermometer() -> SimBackend {
+        let mut sim = SimBackend::new();
+        sim.add_device(SimDevice::new(MMC, "MMC").service(SERVICE, &[CHAR]));
+        sim
+    }
+
+    #[test]
+    fn connect_resolves_services_and_keeps_values() {
+        let mut sim = thermometer();
+        assert!(sim.subscribe(MMC, SERVICE, CHAR).is_err());
+
+        sim.connect(MMC).unwrap();
+        assert_eq!(
+            sim.next_event(Duration::from_secs(1)).unwrap(),
+            Some(BackendEvent::Connected {
+                device: MMC.to_string(),
+                connected: true
+            })
+        );
+        assert_eq!(
+            sim.next_event(Duration::from_secs(1)).unwrap(),
+            Some(BackendEvent::ServicesResolved {
+                device: MMC.to_string()
+            })
+        );
+
+        sim.write_characteristic(MMC, SERVICE, CHAR, &[1, 0])
+            .unwrap();
+        assert_eq!(sim.written(MMC, CHAR), Some(&[1u8, 0][..]));
+        assert_eq!(
+            sim.read_characteristic(MMC, SERVICE, CHAR).unwrap(),
+            vec![1, 0]
+        );
+        assert!(sim
+            .read_characteristic(MMC, BleUuid::from_u16(0x8850), CHAR)
+            .is_err());
+    }
+
+    #[test]
+    fn notifications_stop_after_a_dropped_connection() {
+        let mut sim = thermometer();
+        sim.notify(MMC, CHAR, &[1])
+            .drop_connection(MMC)
+            .notify(MMC, CHAR, &[2]);
+        sim.connect(MMC).unwrap();
+        sim.subscribe(MMC, SERVICE, CHAR).unwrap();
+
+        let mut events = vec![];
+        while let Some(event) = sim.next_event(Duration::from_secs(1)).unwrap() {
+            events.push(event);
+        }
+
+        assert_eq!(
+            &events[2..],
+            &[
+                BackendEvent::Notification {
+                    device: MMC.to_string(),
+                    characteristic: Some(CHAR),
+                    value: vec![1]
+                },
+                BackendEvent::Connected {
+                    device: MMC.to_string(),
+                    connected: false
+                },
+            ]
+        );
+        assert!(!sim.is_connected(MMC));
+        assert!(!sim.is_subscribed(MMC, CHAR));
+    }
+}
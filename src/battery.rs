@@ -0,0 +1,135 @@
+//! Battery Service (0x180F) of the controllers.
+//!
+//! Battery Level (0x2A19) is a single byte, the charge in percent. The
+//! controllers notify it when it changes, it is also read once after each
+//! connection. BlueZ keeps the service to its battery plugin, so the blurz
+//! backend reports `org.bluez.Battery1` as Battery Level instead.
+
+use crate::ble_uuid::BleUuid;
+use crate::joystick::{DeviceStatus, JoystickEvent};
+use std::collections::HashMap;
+
+pub const BATTERY_SERVICE_UUID: BleUuid = BleUuid::from_u16(0x180f);
+pub const BATTERY_LEVEL_UUID: BleUuid = BleUuid::from_u16(0x2a19);
+
+/// Default percentage below which `BatteryMonitor` warns
+pub const LOW_BATTERY_THRESHOLD: u8 = 20;
+
+/// Decode a Battery Level value, `None` if it is not a single byte of at
+/// most 100
+pub fn parse_battery_level(value: &[u8]) -> Option<u8> {
+    match value {
+        [level] if *level <= 100 => Some(*level),
+        _ => None,
+    }
+}
+
+/// Turns the battery levels of the status events into low battery
+/// warnings, once per device each time its level drops below `threshold`
+#[derive(Clone, Debug)]
+pub struct BatteryMonitor {
+    threshold: u8,
+    /// Last known level by device
+    levels: HashMap<String, u8>,
+}
+
+impl Default for BatteryMonitor {
+    fn default() -> BatteryMonitor {
+        BatteryMonitor::new(LOW_BATTERY_THRESHOLD)
+    }
+}
+
+impl BatteryMonitor {
+    pub fn new(threshold: u8) -> BatteryMonitor {
+        BatteryMonitor {
+            threshold,
+            levels: HashMap::new(),
+        }
+    }
+
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    /// Last battery level reported by `device`
+    pub fn level(&self, device: &str) -> Option<u8> {
+        self.levels.get(device).copied()
+    }
+
+    /// A `LowBattery` event if `event` reports a level below the threshold
+    /// and the previous one of the device was not
+    pub fn check(&mut self, event: &JoystickEvent) -> Option<JoystickEvent> {
+        let (device, level) = match event {
+            JoystickEvent::Status(
+                device,
+                DeviceStatus {
+                    battery: Some(level),
+                    ..
+                },
+            ) => (device, *level),
+            _ => return None,
+        };
+        let previous = self.levels.insert(device.clone(), level);
+        // 电量在阈值附近来回跳时只在跌破的那一次报警
+        if level < self.threshold && previous.is_none_or(|previous| previous >= self.threshold) {
+            return Some(JoystickEvent::LowBattery(device.clone(), level));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BELL: &str = "/org/bluez/hci0/dev_E0_7D_EA_00_00_01";
+
+    fn status(battery: Option<u8>) -> JoystickEvent {
+        JoystickEvent::Status(
+            BELL.to_string(),
+            DeviceStatus {
+                connected: true,
+                battery,
+            },
+        )
+    }
+
+    #[test]
+    fn parse_levels() {
+        assert_eq!(parse_battery_level(&[0]), Some(0));
+        assert_eq!(parse_battery_level(&[87]), Some(87));
+        assert_eq!(parse_battery_level(&[100]), Some(100));
+        assert_eq!(parse_battery_level(&[101]), None);
+        assert_eq!(parse_battery_level(&[]), None);
+        assert_eq!(parse_battery_level(&[50, 0]), None);
+    }
+
+    #[test]
+    fn warn_once_per_drop() {
+        let mut monitor = BatteryMonitor::new(20);
+        let low = Some(JoystickEvent::LowBattery(BELL.to_string(), 19));
+
+        assert_eq!(monitor.check(&status(Some(21))), None);
+        assert_eq!(monitor.check(&status(None)), None);
+        assert_eq!(monitor.check(&status(Some(19))), low);
+        assert_eq!(monitor.check(&status(Some(18))), None);
+        assert_eq!(monitor.level(BELL), Some(18));
+
+        // 充电之后再次跌破要重新报警
+        assert_eq!(monitor.check(&status(Some(60))), None);
+        assert_eq!(monitor.check(&status(Some(19))), low);
+        assert_eq!(
+            monitor.check(&JoystickEvent::Home(BELL.to_string(), true)),
+            None
+        );
+    }
+
+    #[test]
+    fn warn_on_first_low_level() {
+        let mut monitor = BatteryMonitor::default();
+        assert_eq!(
+            monitor.check(&status(Some(5))),
+            Some(JoystickEvent::LowBattery(BELL.to_string(), 5))
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use bell_ble_controller::backend::{
+    default_backend, device_address, device_path, BackendEvent, RESOLVE_TIMEOUT,
+};
+use bell_ble_controller::battery::{
+    BatteryMonitor, BATTERY_LEVEL_UUID, BATTERY_SERVICE_UUID, LOW_BATTERY_THRESHOLD,
+};
+use bell_ble_controller::capture::{CaptureWriter, Replay};
+use bell_ble_controller::cli::Options;
+use bell_ble_controller::controller::{ControllerManager, PlayerEvent, CLAIM_TIMEOUT};
+use bell_ble_controller::error::Error;
+use bell_ble_controller::input::{InputEvent, InputTracker};
+/// Run this command to turn on bluetooth first:
+/// bluetoothctl power on
+use bell_ble_controller::joystick::{
+    discover_joysticks, handle_ble_event, joystick_filter, JoystickEvent, BELL_CHAR_UUID,
+    BELL_SERVICE_UUID,
+};
+use bell_ble_controller::logging::{self, EVENTS};
+use bell_ble_controller::supervisor::{Backoff, Retry, StateChange, Supervisor};
+use bell_ble_controller::uinput::{UinputDevice, VirtualGamepad};
+use log::{debug, error, info, trace, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::{Duration, Instant};
+
+const DEFAULT_SLOTS: &str = "bell-slots.json";
+const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// One uinput gamepad per connected controller, created on first input
+#[derive(Default)]
+struct Gamepads {
+    pads: HashMap<String, VirtualGamepad<UinputDevice>>,
+}
+
+impl Gamepads {
+    fn emit(&mut self, object_path: &str, player: usize, events: &[InputEvent]) {
+        let device = device_path(object_path).to_string();
+        if !self.pads.contains_key(&device) {
+            // 没有输入就不建设备, 比如还没认领的手柄
+            if events.is_empty() {
+                return;
+            }
+            match UinputDevice::create(&format!("Bell Controller {}", player)) {
+                Ok(pad) => {
+                    self.pads.insert(device.clone(), VirtualGamepad::new(pad));
+                }
+                Err(e) => {
+                    error!("Failed to create uinput device for {}: {:?}", device, e);
+                    return;
+                }
+            }
+        }
+        if let Some(pad) = self.pads.get_mut(&device) {
+            if let Err(e) = pad.emit(events) {
+                warn!("Failed to write uinput events for {}: {:?}", device, e);
+            }
+        }
+    }
+
+    fn remove(&mut self, object_path: &str) {
+        self.pads.remove(device_path(object_path));
+    }
+}
+
+/// Decoding from notification to gamepad, shared by live and replayed
+/// events
+struct Pipeline {
+    controllers: ControllerManager,
+    tracker: InputTracker,
+    battery: BatteryMonitor,
+    gamepads: Option<Gamepads>,
+    recorder: Option<CaptureWriter<BufWriter<File>>>,
+}
+
+impl Pipeline {
+    fn handle(&mut self, event: BackendEvent) {
+        trace!(target: EVENTS, "recv: {:?}", event);
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.record(&event) {
+                warn!("Failed to record event: {:?}", e);
+            }
+        }
+        if let BackendEvent::Connected {
+            device,
+            connected: false,
+        } = &event
+        {
+            let inputs = self.tracker.disconnect(device);
+            for input in inputs.iter() {
+                debug!(target: EVENTS, "recv input event: {:?}", input);
+            }
+            let player = self.controllers.player(&device_address(device));
+            if let (Some(gamepads), Some(player)) = (self.gamepads.as_mut(), player) {
+                gamepads.emit(device, player, &inputs);
+                gamepads.remove(device);
+            }
+        }
+        if let Some(event) = handle_ble_event(Some(event)) {
+            // 电量低于阈值时再多一个 LowBattery 事件
+            let low_battery = self.battery.check(&event);
+            self.handle_joystick(event);
+            if let Some(event) = low_battery {
+                self.handle_joystick(event);
+            }
+        }
+    }
+
+    fn handle_joystick(&mut self, event: JoystickEvent) {
+        match &event {
+            JoystickEvent::Status(device, status) => {
+                info!(target: EVENTS, "{}: {:?}", device, status);
+            }
+            JoystickEvent::LowBattery(device, level) => {
+                warn!(target: EVENTS, "{}: battery low, {}%", device, level);
+            }
+            _ => {}
+        }
+        for player_event in self.controllers.handle(event) {
+            match player_event {
+                PlayerEvent::Claimed { player, address } => {
+                    info!("{} is player {}", address, player);
+                }
+                PlayerEvent::Input { player, event } => {
+                    debug!(target: EVENTS, "player {} key event: {:?}", player, event);
+                    let (device, inputs) = self.tracker.update(&event);
+                    for input in inputs.iter() {
+                        debug!(target: EVENTS, "recv input event: {}: {:?}", device, input);
+                    }
+                    if let Some(gamepads) = self.gamepads.as_mut() {
+                        gamepads.emit(&device, player, &inputs);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn log_changes(changes: &[StateChange]) {
+    for change in changes.iter() {
+        info!(
+            target: EVENTS,
+            "{}: {:?} -> {:?}", change.device, change.from, change.to
+        );
+    }
+}
+
+fn main() -> Result<(), Error> {
+    let options = Options::from_env(joystick_filter());
+    logging::init(options.verbosity);
+
+    info!("Enable Bluetooth power before running this method,");
+    info!("bluetoothctl power on");
+
+    // --uinput 把手柄注册成系统的虚拟游戏手柄
+    let gamepads = if options.uinput {
+        Some(Gamepads::default())
+    } else {
+        None
+    };
+    let recorder = options
+        .record
+        .as_ref()
+        .map(CaptureWriter::create)
+        .transpose()?;
+    // 玩家位置按 MAC 地址保存, 重启之后不变. 回放时只放在内存里, 不改动保存的位置
+    let mut controllers = if options.replay.is_some() {
+        ControllerManager::new()
+    } else {
+        ControllerManager::open(options.slots.as_deref().unwrap_or(DEFAULT_SLOTS))?
+    };
+    if options.claim {
+        info!("Press Home on each controller to claim player 1 to 4");
+        controllers.start_claim(CLAIM_TIMEOUT);
+    }
+    let mut pipeline = Pipeline {
+        controllers,
+        tracker: InputTracker::new(),
+        battery: BatteryMonitor::new(options.low_battery.unwrap_or(LOW_BATTERY_THRESHOLD)),
+        gamepads,
+        recorder,
+    };
+
+    // --replay FILE [--speed N] 回放录下的通知, 不需要蓝牙
+    if let Some(path) = &options.replay {
+        for event in Replay::open(path, options.speed)? {
+            pipeline.handle(event);
+        }
+        return Ok(());
+    }
+
+    let mut backend = default_backend(options.adapter.as_deref())?;
+
+    backend.set_scan_filter(options.scan_filter())?;
+
+    let scan_timeout = options.scan_timeout.unwrap_or(Duration::from_secs(10));
+    let joysticks = discover_joysticks(
+        backend.as_mut(),
+        &options.filter,
+        options.count,
+        scan_timeout,
+    )?;
+
+    if joysticks.is_empty() {
+        error!("No joysticks found, exit");
+        return Err(Error::DeviceNotFound("bell controller".to_string()));
+    }
+
+    // 断线之后自动重连, 并重新打开 885a 和电量的通知
+    let mut supervisor = Supervisor::new(BELL_SERVICE_UUID, BELL_CHAR_UUID, Backoff::default())
+        .resolve_timeout(options.resolve_timeout.unwrap_or(RESOLVE_TIMEOUT))
+        .also_notify(BATTERY_SERVICE_UUID, BATTERY_LEVEL_UUID);
+    for device in joysticks.iter() {
+        supervisor.add(&device.advertisement.id, Instant::now());
+    }
+
+    let mut retry = Retry::new(Backoff::default());
+    let mut stats_at = Instant::now();
+    loop {
+        // 定期打印两种通知方式的统计, 方便比较延迟
+        if stats_at.elapsed() >= STATS_INTERVAL {
+            stats_at = Instant::now();
+            for (path, stats) in backend.notify_stats() {
+                if stats.packets > 0 {
+                    info!(target: EVENTS, "{}: {}", path, stats);
+                }
+            }
+        }
+        log_changes(&supervisor.poll(backend.as_mut(), Instant::now()));
+        for event in supervisor.take_events() {
+            log_changes(&supervisor.handle_event(&event, Instant::now()));
+            pipeline.handle(event);
+        }
+        match backend.next_event(Duration::from_secs(1)) {
+            Ok(Some(event)) => {
+                retry.succeeded();
+                log_changes(&supervisor.handle_event(&event, Instant::now()));
+                pipeline.handle(event);
+            }
+            Ok(None) => retry.succeeded(),
+            Err(e) => {
+                warn!(target: EVENTS, "Failed to receive event: {}", e);
+                retry.failed(e)?;
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+extern crate blurz;
+
+use bell_ble_controller::backend::blurz::BlurzBackend;
+use bell_ble_controller::backend::{
+    connect_and_resolve, device_address, Advertisement, BleBackend, ScanFilter, RESOLVE_TIMEOUT,
+};
+use bell_ble_controller::cli::{self, Options};
+use bell_ble_controller::discovery::find_device;
+use bell_ble_controller::gatt::dump_device;
+use bell_ble_controller::gatt_dump::{diff, GattDump};
+use bell_ble_controller::joystick::{joystick_filter, BELL_CHAR_UUID};
+use bell_ble_controller::logging;
+use bell_ble_controller::notify::NotifySocket;
+use bell_ble_controller::pairing::{bonded_devices, forget_device, pair_and_trust, PAIR_TIMEOUT};
+use blurz::bluetooth_device::BluetoothDevice as Device;
+use blurz::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic as Characteristic;
+use blurz::bluetooth_gatt_descriptor::BluetoothGATTDescriptor as Descriptor;
+use blurz::bluetooth_gatt_service::BluetoothGATTService as Service;
+use log::debug;
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufReader, Write};
+use std::process;
+use std::time::Duration;
+
+/// The first bell device found by a scan
+fn scan_bell_device(backend: &mut BlurzBackend) -> Result<Advertisement, Box<dyn Error>> {
+    let filter = joystick_filter();
+    // 只扫 LE 设备, 附近的经典蓝牙设备不用管
+    backend.set_scan_filter(ScanFilter {
+        services: filter.service.into_iter().collect(),
+        ..Default::default()
+    })?;
+    let device =
+        find_device(backend, &filter, Duration::from_secs(10))?.ok_or("No bell device found")?;
+    debug!(
+        "Found bell device {} {:?}, services {:?}",
+        device.id, device.name, device.services
+    );
+    Ok(device)
+}
+
+fn test2() -> Result<(), Box<dyn Error>> {
+    let mut backend = BlurzBackend::new(None)?;
+    let id = scan_bell_device(&mut backend)?.id;
+    if let Err(e) = connect_and_resolve(&mut backend, &id, RESOLVE_TIMEOUT) {
+        eprintln!("conn err: {}", e);
+        return Err(Box::from("No connectable device found"));
+    }
+    println!("Device connected: {}", id);
+    let bt_session = backend.session();
+    let device = Device::new(bt_session, id);
+    println!("checking gatt...");
+    match device.get_gatt_services() {
+        Ok(services) => println!("GATT services: {:?}", services),
+        Err(e) => eprintln!("{:?}", e),
+    }
+
+    let services = device.get_gatt_services()?;
+
+    let mut ch = None;
+    for service in services {
+        let s = Service::new(bt_session, service.clone());
+        println!("S uuid: {:?}", s.get_uuid());
+        let characteristics = s.get_gatt_characteristics()?;
+        for characteristic in characteristics {
+            let c = Characteristic::new(bt_session, characteristic.clone());
+            println!("C uuid: {:?}", c.get_uuid());
+            println!("Value: {:?}", c.read_value(None));
+            if let Ok(uuid) = c.get_uuid() {
+                if uuid.parse() == Ok(BELL_CHAR_UUID) {
+                    ch = Some(c.clone());
+                    break;
+                }
+            }
+
+            let descriptors = c.get_gatt_descriptors()?;
+            for descriptor in descriptors {
+                let d = Descriptor::new(bt_session, descriptor.clone());
+                println!("D {:?}", d);
+                println!("Value: {:?}", d.read_value(None));
+            }
+        }
+    }
+
+    if let Some(ch) = ch {
+        if let Ok((fd, mtu)) = ch.acquire_notify() {
+            println!("acquire_notify success, mtu {}", mtu);
+            // SEQPACKET socket, 每次 read 正好一个通知
+            let mut socket = unsafe { NotifySocket::from_raw_fd(fd.into_fd(), mtu) };
+            for _ in 0..10 {
+                println!("notify: {:x?}", socket.read_packet()?);
+            }
+        }
+
+    // let r = ch.start_notify();
+    // println!("start_notify {:?}", r);
+
+    // loop {
+    //     if let Ok(r) = ch.read_value(None) {
+    //         println!("get: {:?}", r);
+    //     }
+
+    //     thread::sleep(Duration::from_secs(100));
+    // }
+    } else {
+        eprintln!("Character not found!");
+    }
+
+    let r = device.disconnect();
+    println!("disconn {:?}", r);
+    Ok(())
+}
+
+const COMMANDS: &str = "\
+Usage: blue [COMMAND]
+
+Without a command, connect to the first bell device and read ten
+notifications.
+
+Commands:
+    gatt-dump [OPTIONS]          print the GATT database of a device as JSON
+    gatt-diff OLD NEW            compare two dumps, exit with 1 if they differ
+    pair [OPTIONS]               pair with a device and trust it
+    unpair ADDRESS [OPTIONS]     remove a device and its keys, also `forget`
+    list-bonded [OPTIONS]        list the paired devices
+";
+
+/// Scan until the device matching the options shows up
+fn scan_for_device(
+    backend: &mut BlurzBackend,
+    options: &Options,
+) -> Result<Advertisement, Box<dyn Error>> {
+    backend.set_scan_filter(options.scan_filter())?;
+    let scan_timeout = options.scan_timeout.unwrap_or(Duration::from_secs(5));
+    Ok(find_device(backend, &options.filter, scan_timeout)?.ok_or("No matching device found")?)
+}
+
+/// Connect to the device matching the options and print its GATT database
+fn gatt_dump(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, joystick_filter())?;
+    logging::init(options.verbosity);
+    let mut backend = BlurzBackend::new(options.adapter.as_deref())?;
+    let device = scan_for_device(&mut backend, &options)?;
+
+    let resolve_timeout = options.resolve_timeout.unwrap_or(RESOLVE_TIMEOUT);
+    connect_and_resolve(&mut backend, &device.id, resolve_timeout)?;
+    let dump = dump_device(
+        &Device::new(backend.session(), device.id.clone()),
+        backend.session(),
+    );
+    backend.disconnect(&device.id).ok();
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    serde_json::to_writer_pretty(&mut stdout, &dump?)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+/// Pair with the device matching the options, trust it and print its
+/// bonding state
+fn pair(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, joystick_filter())?;
+    logging::init(options.verbosity);
+    let mut backend = BlurzBackend::new(options.adapter.as_deref())?;
+    let device = scan_for_device(&mut backend, &options)?;
+    let state = pair_and_trust(backend.session(), &device.id, PAIR_TIMEOUT, &mut |_| {})?;
+    println!(
+        "{} {}: {}",
+        device.address,
+        device.name.unwrap_or_default(),
+        state
+    );
+    if !state.bonded {
+        return Err(Box::from(format!("{} is not bonded", device.address)));
+    }
+    Ok(())
+}
+
+/// Remove the device with the given address from the adapter
+fn unpair(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let (address, args) = match args.split_first() {
+        Some((address, args)) if !address.starts_with('-') => (address.clone(), args.to_vec()),
+        _ => return Err(Box::from("unpair takes the address of the device")),
+    };
+    let options = Options::parse(args, joystick_filter())?;
+    logging::init(options.verbosity);
+    let mut backend = BlurzBackend::new(options.adapter.as_deref())?;
+    let device = backend
+        .known_devices()?
+        .into_iter()
+        .find(|d| d.address.eq_ignore_ascii_case(&address))
+        .ok_or_else(|| format!("{} is not known to the adapter", address))?;
+    forget_device(backend.session(), backend.adapter(), &device.id)?;
+    println!("Removed {}", device.address);
+    Ok(())
+}
+
+/// Print the paired devices of the adapter with their bonding state
+fn list_bonded(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let options = Options::parse(args, joystick_filter())?;
+    logging::init(options.verbosity);
+    let backend = BlurzBackend::new(options.adapter.as_deref())?;
+    for (path, state) in bonded_devices(backend.session(), backend.adapter())? {
+        let name = Device::new(backend.session(), path.clone())
+            .get_name()
+            .unwrap_or_default();
+        println!("{} {}: {}", device_address(&path), name, state);
+    }
+    Ok(())
+}
+
+fn read_dump(path: &str) -> Result<GattDump, Box<dyn Error>> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    Ok(serde_json::from_reader(BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))?)
+}
+
+/// Print what changed between two dumps
+fn gatt_diff(args: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let (old, new) = match args.as_slice() {
+        [old, new] => (read_dump(old)?, read_dump(new)?),
+        _ => return Err(Box::from("gatt-diff takes two dump files")),
+    };
+    let changes = diff(&old, &new);
+    for change in changes.iter() {
+        println!("{}", change);
+    }
+    if !changes.is_empty() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let command = args.next();
+    let args = args.collect::<Vec<_>>();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help")
+        || matches!(command.as_deref(), Some("-h") | Some("--help"))
+    {
+        println!("{}\n{}", COMMANDS, cli::USAGE);
+        return;
+    }
+
+    let r = match command.as_deref() {
+        None => test2(),
+        Some("gatt-dump") => gatt_dump(args),
+        Some("gatt-diff") => gatt_diff(args),
+        Some("pair") => pair(args),
+        Some("unpair") | Some("forget") => unpair(args),
+        Some("list-bonded") => list_bonded(args),
+        Some(command) => Err(Box::from(format!("Unknown command {}", command))),
+    };
+    if let Err(e) = r {
+        eprintln!("Error: {}", e);
+        process::exit(2);
+    }
+}
@@ -0,0 +1,68 @@
+use bell_ble_controller::backend::btleplug::BtleplugBackend;
+use bell_ble_controller::backend::{BleBackend, RESOLVE_TIMEOUT};
+use bell_ble_controller::cli::Options;
+use bell_ble_controller::error::Error;
+use bell_ble_controller::joystick::{
+    connect_joystick, get_joysticks_with_event, handle_ble_event, joystick_filter,
+};
+use bell_ble_controller::logging::{self, EVENTS};
+use bell_ble_controller::supervisor::{Backoff, Retry};
+use log::{error, info, warn};
+use std::time::Duration;
+
+fn main() -> Result<(), Error> {
+    let options = Options::from_env(joystick_filter());
+    logging::init(options.verbosity);
+    let mut backend = BtleplugBackend::new(options.adapter.as_deref())?;
+    let scan_timeout = options.scan_timeout.unwrap_or(Duration::from_secs(5));
+
+    let mut retry = Retry::new(Backoff::default());
+    let controller = loop {
+        let joysticks =
+            match get_joysticks_with_event(&mut backend, &options.filter, Some(1), scan_timeout) {
+                Ok(joysticks) => joysticks,
+                Err(e) => {
+                    warn!("Failed to scan: {}", e);
+                    retry.failed(e)?;
+                    continue;
+                }
+            };
+        retry.succeeded();
+        if let Some(device) = joysticks.into_iter().next() {
+            break device;
+        } else {
+            warn!("no device found, wait");
+        }
+    };
+
+    let resolve_timeout = options.resolve_timeout.unwrap_or(RESOLVE_TIMEOUT);
+    let events = match connect_joystick(&mut backend, &controller.id, resolve_timeout) {
+        Ok(events) => events,
+        Err(e) => {
+            error!("Failed to connect {:?}: {}", controller, e);
+            return Err(e);
+        }
+    };
+    info!(target: EVENTS, "{:?} connected", controller);
+    // 连接期间收到的其他手柄的事件
+    for event in events {
+        if let Some(event) = handle_ble_event(Some(event)) {
+            info!(target: EVENTS, "Ble msg: {:?}", event);
+        }
+    }
+
+    loop {
+        match backend.next_event(Duration::from_secs(1)) {
+            Ok(event) => {
+                retry.succeeded();
+                if let Some(event) = handle_ble_event(event) {
+                    info!(target: EVENTS, "Ble msg: {:?}", event);
+                }
+            }
+            Err(e) => {
+                warn!(target: EVENTS, "Failed to receive event: {}", e);
+                retry.failed(e)?;
+            }
+        }
+    }
+}
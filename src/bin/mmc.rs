@@ -0,0 +1,104 @@
+use bell_ble_controller::backend::{
+    connect_and_resolve, default_backend, BackendEvent, RESOLVE_TIMEOUT,
+};
+use bell_ble_controller::capture::{CaptureWriter, Replay};
+use bell_ble_controller::cli::Options;
+use bell_ble_controller::discovery::DeviceFilter;
+use bell_ble_controller::error::Error;
+use bell_ble_controller::logging::{self, EVENTS};
+use bell_ble_controller::supervisor::{Backoff, Retry};
+use bell_ble_controller::thermometer::{
+    enable_thermometer_notify, find_thermometer, handle_mmc_event, handle_thermometer_event,
+    MMC_SERVICE_UUID,
+};
+use log::{error, info, trace, warn};
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::Duration;
+
+// 默认的 mmc 地址
+const MMC_ADDRESS: &str = "00:81:F9:DF:B0:40";
+
+/// The standard Health Thermometer layout, or with `--mmc-calibration` the
+/// 6 byte vendor frames of the MMC
+fn log_temperature(event: BackendEvent, mmc_calibration: bool) {
+    if mmc_calibration {
+        if let Some((raw, _, _, t)) = handle_mmc_event(Some(event)) {
+            info!("Raw t: {}, calibrated: {}", raw, t);
+        }
+    } else if let Some(m) = handle_thermometer_event(Some(event)) {
+        info!("Temperature: {:?} {:?}", m.temperature, m.unit);
+    }
+}
+
+fn handle(
+    recorder: &mut Option<CaptureWriter<BufWriter<File>>>,
+    event: BackendEvent,
+    mmc_calibration: bool,
+) {
+    trace!(target: EVENTS, "recv: {:?}", event);
+    if let Some(recorder) = recorder.as_mut() {
+        if let Err(e) = recorder.record(&event) {
+            warn!("Failed to record event: {}", e);
+        }
+    }
+    log_temperature(event, mmc_calibration);
+}
+
+fn main() -> Result<(), Error> {
+    let options = Options::from_env(DeviceFilter {
+        service: Some(MMC_SERVICE_UUID),
+        ..DeviceFilter::address(MMC_ADDRESS)
+    });
+    logging::init(options.verbosity);
+
+    // --replay FILE [--speed N] 回放录下的通知, 不需要蓝牙
+    if let Some(path) = &options.replay {
+        for event in Replay::open(path, options.speed)? {
+            log_temperature(event, options.mmc_calibration);
+        }
+        return Ok(());
+    }
+    let mut recorder = options
+        .record
+        .as_ref()
+        .map(CaptureWriter::create)
+        .transpose()?;
+
+    let mut backend = default_backend(options.adapter.as_deref())?;
+
+    backend.set_scan_filter(options.scan_filter())?;
+
+    // 最多扫描几秒, 找到设备就停
+    let scan_timeout = options.scan_timeout.unwrap_or(Duration::from_secs(5));
+    let device = match find_thermometer(backend.as_mut(), &options.filter, scan_timeout)? {
+        Some(device) => device.id,
+        None => {
+            error!("Thermometer not found, exit");
+            return Err(Error::DeviceNotFound(MMC_ADDRESS.to_string()));
+        }
+    };
+
+    let resolve_timeout = options.resolve_timeout.unwrap_or(RESOLVE_TIMEOUT);
+    let events = connect_and_resolve(backend.as_mut(), &device, resolve_timeout)?;
+    info!(target: EVENTS, "Connected!");
+
+    enable_thermometer_notify(backend.as_mut(), &device)?;
+    for event in events {
+        handle(&mut recorder, event, options.mmc_calibration);
+    }
+    let mut retry = Retry::new(Backoff::default());
+    loop {
+        match backend.next_event(Duration::from_secs(1)) {
+            Ok(Some(event)) => {
+                retry.succeeded();
+                handle(&mut recorder, event, options.mmc_calibration);
+            }
+            Ok(None) => retry.succeeded(),
+            Err(e) => {
+                warn!(target: EVENTS, "Failed to receive event: {}", e);
+                retry.failed(e)?;
+            }
+        }
+    }
+}
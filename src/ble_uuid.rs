@@ -0,0 +1,215 @@
+//! Service, characteristic and descriptor UUIDs.
+//!
+//! The SIG assigned numbers are 16 or 32 bit shorthands for UUIDs built on
+//! the Bluetooth base UUID 00000000-0000-1000-8000-00805f9b34fb, so "8850"
+//! and "00008850-0000-1000-8000-00805f9b34fb" are the same `BleUuid`.
+//! Vendor UUIDs outside the base only have the full form.
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+/// The Bluetooth base UUID, the assigned numbers fill in the first 32 bits
+const BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5f9b_34fb;
+const BASE_MASK: u128 = (1 << 96) - 1;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BleUuid(u128);
+
+impl BleUuid {
+    /// A 16 bit assigned number
+    pub const fn from_u16(short: u16) -> BleUuid {
+        BleUuid::from_u32(short as u32)
+    }
+
+    /// A 32 bit assigned number
+    pub const fn from_u32(short: u32) -> BleUuid {
+        BleUuid(BASE_UUID | (short as u128) << 96)
+    }
+
+    pub const fn from_u128(uuid: u128) -> BleUuid {
+        BleUuid(uuid)
+    }
+
+    /// A UUID stored little endian, as the HCI socket stacks keep them
+    pub const fn from_le_bytes(bytes: [u8; 16]) -> BleUuid {
+        BleUuid(u128::from_le_bytes(bytes))
+    }
+
+    pub const fn as_u128(self) -> u128 {
+        self.0
+    }
+
+    /// The 32 bit assigned number, `None` for UUIDs not on the base UUID
+    pub fn to_u32(self) -> Option<u32> {
+        if self.0 & BASE_MASK == BASE_UUID {
+            Some((self.0 >> 96) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// The 16 bit assigned number, `None` for all other UUIDs
+    pub fn to_u16(self) -> Option<u16> {
+        self.to_u32().and_then(|short| u16::try_from(short).ok())
+    }
+
+    /// The shortest form, e.g. "8850" or a full UUID for vendor ones
+    pub fn to_short_string(self) -> String {
+        match (self.to_u16(), self.to_u32()) {
+            (Some(short), _) => format!("{:04x}", short),
+            (None, Some(short)) => format!("{:08x}", short),
+            _ => self.to_string(),
+        }
+    }
+}
+
+/// The full lower case form, e.g. "0000885a-0000-1000-8000-00805f9b34fb"
+impl fmt::Display for BleUuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hex = format!("{:032x}", self.0);
+        write!(
+            f,
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )
+    }
+}
+
+impl fmt::Debug for BleUuid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "BleUuid({})", self.to_short_string())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseUuidError(String);
+
+impl fmt::Display for ParseUuidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid UUID {:?}", self.0)
+    }
+}
+
+impl std::error::Error for ParseUuidError {}
+
+/// Parses "8850", "0x8850", the 32 bit "00008850" and full UUIDs with or
+/// without dashes, in either case
+impl FromStr for BleUuid {
+    type Err = ParseUuidError;
+
+    fn from_str(s: &str) -> Result<BleUuid, ParseUuidError> {
+        let error = || ParseUuidError(s.to_string());
+        let short = s
+            .strip_prefix("0x")
+            .or_else(|| s.strip_prefix("0X"))
+            .unwrap_or(s);
+        // from_str_radix 允许前面带 '+', 这里只接受十六进制数字
+        if !short.chars().all(|c| c.is_ascii_hexdigit()) {
+            let dashes = [8, 13, 18, 23];
+            let valid = s.len() == 36
+                && s.char_indices().all(|(i, c)| {
+                    if dashes.contains(&i) {
+                        c == '-'
+                    } else {
+                        c.is_ascii_hexdigit()
+                    }
+                });
+            if !valid {
+                return Err(error());
+            }
+            let hex = s.replace('-', "");
+            return u128::from_str_radix(&hex, 16)
+                .map(BleUuid)
+                .map_err(|_| error());
+        }
+        match short.len() {
+            4 => u16::from_str_radix(short, 16).map(BleUuid::from_u16),
+            8 => u32::from_str_radix(short, 16).map(BleUuid::from_u32),
+            32 if short.len() == s.len() => u128::from_str_radix(short, 16).map(BleUuid),
+            _ => return Err(error()),
+        }
+        .map_err(|_| error())
+    }
+}
+
+impl Serialize for BleUuid {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BleUuid {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<BleUuid, D::Error> {
+        String::deserialize(d)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BELL_SERVICE: BleUuid = BleUuid::from_u16(0x8850);
+
+    #[test]
+    fn short_and_full_forms() {
+        assert_eq!(
+            BELL_SERVICE.to_string(),
+            "00008850-0000-1000-8000-00805f9b34fb"
+        );
+        assert_eq!(BELL_SERVICE.to_short_string(), "8850");
+        assert_eq!(BELL_SERVICE.to_u16(), Some(0x8850));
+        assert_eq!(BleUuid::from_u32(0x1234_5678).to_short_string(), "12345678");
+        assert_eq!(BleUuid::from_u32(0x1234_5678).to_u16(), None);
+
+        let vendor = BleUuid::from_u128(0x6e40_0001_b5a3_f393_e0a9_e50e_24dc_ca9e);
+        assert_eq!(vendor.to_u32(), None);
+        assert_eq!(
+            vendor.to_short_string(),
+            "6e400001-b5a3-f393-e0a9-e50e24dcca9e"
+        );
+
+        let bytes = 0x0000_8850_0000_1000_8000_0080_5f9b_34fbu128.to_le_bytes();
+        assert_eq!(BleUuid::from_le_bytes(bytes), BELL_SERVICE);
+    }
+
+    #[test]
+    fn parse_uuids() {
+        for s in [
+            "8850",
+            "0x8850",
+            "00008850",
+            "00008850-0000-1000-8000-00805f9b34fb",
+            "00008850-0000-1000-8000-00805F9B34FB",
+            "0000885000001000800000805f9b34fb",
+        ]
+        .iter()
+        {
+            assert_eq!(s.parse::<BleUuid>(), Ok(BELL_SERVICE), "{}", s);
+        }
+        for s in [
+            "",
+            "885",
+            "+885",
+            "0x00008850000010008000",
+            "00008850-0000-1000-8000",
+            "bell",
+        ]
+        .iter()
+        {
+            assert!(s.parse::<BleUuid>().is_err(), "{}", s);
+        }
+
+        let json = serde_json::to_string(&BELL_SERVICE).unwrap();
+        assert_eq!(json, r#""00008850-0000-1000-8000-00805f9b34fb""#);
+        assert_eq!(
+            serde_json::from_str::<BleUuid>(&json).unwrap(),
+            BELL_SERVICE
+        );
+    }
+}
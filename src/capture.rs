@@ -0,0 +1,186 @@
+//! Notification capture files, one JSON object per line.
+//!
+//! ```text
+//! {"t_ms":0,"object_path":"/org/bluez/hci0/dev_E0_7D_EA_00_00_01","value":[128,128,128,128,0,0,0,0,0,0]}
+//! ```
+
+use crate::backend::BackendEvent;
+use crate::ble_uuid::BleUuid;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRecord {
+    /// Milliseconds since the recording started, monotonic
+    pub t_ms: u64,
+    pub object_path: String,
+    /// Older captures do not have it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub characteristic: Option<BleUuid>,
+    pub value: Vec<u8>,
+}
+
+impl CaptureRecord {
+    pub fn event(&self) -> BackendEvent {
+        BackendEvent::Notification {
+            device: self.object_path.clone(),
+            characteristic: self.characteristic,
+            value: self.value.clone(),
+        }
+    }
+}
+
+/// Appends every notification to a capture
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    start: Instant,
+}
+
+impl CaptureWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<CaptureWriter<BufWriter<File>>> {
+        Ok(CaptureWriter::new(BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(writer: W) -> CaptureWriter<W> {
+        CaptureWriter {
+            writer,
+            start: Instant::now(),
+        }
+    }
+
+    /// Write the event if it is a notification, other events are skipped
+    pub fn record(&mut self, event: &BackendEvent) -> io::Result<()> {
+        if let BackendEvent::Notification {
+            device,
+            characteristic,
+            value,
+        } = event
+        {
+            let record = CaptureRecord {
+                t_ms: self.start.elapsed().as_millis() as u64,
+                object_path: device.clone(),
+                characteristic: *characteristic,
+                value: value.clone(),
+            };
+            serde_json::to_writer(&mut self.writer, &record)?;
+            self.writer.write_all(b"\n")?;
+            // 每行都刷新, 中途被杀掉也不丢数据
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Parse a capture, empty lines are skipped
+pub fn read_capture<R: BufRead>(reader: R) -> io::Result<Vec<CaptureRecord>> {
+    let mut records = vec![];
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e))
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Plays the records back as notification events, keeping their timing
+/// divided by `speed`. A speed of 0 replays without waiting.
+pub struct Replay {
+    records: std::vec::IntoIter<CaptureRecord>,
+    speed: f64,
+    start: Instant,
+}
+
+impl Replay {
+    pub fn new(records: Vec<CaptureRecord>, speed: f64) -> Replay {
+        Replay {
+            records: records.into_iter(),
+            speed,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, speed: f64) -> io::Result<Replay> {
+        let records = read_capture(BufReader::new(File::open(path)?))?;
+        Ok(Replay::new(records, speed))
+    }
+}
+
+impl Iterator for Replay {
+    type Item = BackendEvent;
+
+    fn next(&mut self) -> Option<BackendEvent> {
+        let record = self.records.next()?;
+        if self.speed > 0.0 {
+            // 速度太小时算出的时间超出 Duration 的范围, 不再等待
+            let due = Duration::try_from_secs_f64(record.t_ms as f64 / 1000.0 / self.speed).ok();
+            if let Some(wait) = due.and_then(|due| due.checked_sub(self.start.elapsed())) {
+                thread::sleep(wait);
+            }
+        }
+        Some(record.event())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: &str = "/org/bluez/hci0/dev_E0_7D_EA_00_00_01";
+
+    fn notification(value: &[u8]) -> BackendEvent {
+        BackendEvent::Notification {
+            device: DEVICE.to_string(),
+            characteristic: Some(BleUuid::from_u16(0x885a)),
+            value: value.to_vec(),
+        }
+    }
+
+    #[test]
+    fn record_and_replay_round_trip() {
+        let mut writer = CaptureWriter::new(vec![]);
+        writer.record(&notification(&[8, 0, 0])).unwrap();
+        writer
+            .record(&BackendEvent::ServicesResolved {
+                device: DEVICE.to_string(),
+            })
+            .unwrap();
+        writer.record(&notification(&[0, 0, 0])).unwrap();
+
+        let capture = writer.into_inner();
+        let records = read_capture(capture.as_slice()).unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].t_ms <= records[1].t_ms);
+
+        let events = Replay::new(records, 0.0).collect::<Vec<_>>();
+        assert_eq!(
+            events,
+            vec![notification(&[8, 0, 0]), notification(&[0, 0, 0])]
+        );
+    }
+
+    #[test]
+    fn invalid_line_is_reported() {
+        let capture = format!(
+            "{{\"t_ms\":0,\"object_path\":\"{}\",\"value\":[8,0,0]}}\n\nnot json\n",
+            DEVICE
+        );
+        let e = read_capture(capture.as_bytes()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().starts_with("line 3:"));
+    }
+}
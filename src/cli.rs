@@ -0,0 +1,328 @@
+//! Command line options shared by the binaries.
+
+use crate::backend::ScanFilter;
+use crate::discovery::DeviceFilter;
+use regex::Regex;
+use std::env;
+use std::process;
+use std::time::Duration;
+
+pub const USAGE: &str = "\
+Options:
+    --address AA:BB:CC:DD:EE:FF  use the device with this MAC address, can be repeated
+    --name-pattern REGEX         use the devices whose name matches REGEX
+    --service-uuid UUID          use the devices advertising this service
+    --min-rssi DBM               ignore devices with a weaker signal, e.g. -80
+    --max-pathloss DB            ignore devices further away, instead of --min-rssi
+    --count N                    stop scanning once N devices are found
+    --adapter NAME               bluetooth adapter, e.g. hci1
+    --scan-timeout SECONDS       how long to scan for devices
+    --resolve-timeout SECONDS    how long to wait for the services after connecting
+    --uinput                     register the controllers as uinput gamepads
+    --slots FILE                 where the player slots are saved
+    --claim                      reassign the player slots by pressing Home
+    --mmc-calibration            decode the MMC vendor frames instead of the standard layout
+    --low-battery PERCENT        warn when a battery drops below PERCENT, default 20
+    --record FILE                write the notifications to FILE
+    --replay FILE                decode the notifications in FILE instead
+    --speed N                    replay speed, 0 for as fast as possible
+    -v, --verbose                log more, twice for raw packets
+    -q, --quiet                  log less, only warnings and errors
+    -h, --help                   print this help";
+
+/// Slowest replay speed, `--speed 0` aside
+const MIN_SPEED: f64 = 0.001;
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub filter: DeviceFilter,
+    pub adapter: Option<String>,
+    /// Stop scanning after this many matching devices
+    pub count: Option<usize>,
+    /// Largest path loss for the BlueZ discovery filter
+    pub pathloss: Option<u16>,
+    pub scan_timeout: Option<Duration>,
+    pub resolve_timeout: Option<Duration>,
+    pub uinput: bool,
+    pub slots: Option<String>,
+    pub claim: bool,
+    /// Decode the thermometer with `mmc_calibrate`
+    pub mmc_calibration: bool,
+    /// Battery level for the low battery warning, see `BatteryMonitor`
+    pub low_battery: Option<u8>,
+    pub record: Option<String>,
+    pub replay: Option<String>,
+    pub speed: f64,
+    /// Number of `-v` less the number of `-q`, see `logging::level`
+    pub verbosity: i8,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            filter: DeviceFilter::default(),
+            adapter: None,
+            count: None,
+            pathloss: None,
+            scan_timeout: None,
+            resolve_timeout: None,
+            uinput: false,
+            slots: None,
+            claim: false,
+            mmc_calibration: false,
+            low_battery: None,
+            record: None,
+            replay: None,
+            speed: 1.0,
+            verbosity: 0,
+        }
+    }
+}
+
+impl Options {
+    /// Parse the arguments without the program name. `filter` picks the
+    /// devices when neither `--address` nor `--name-pattern` is given.
+    pub fn parse<I: IntoIterator<Item = String>>(
+        args: I,
+        filter: DeviceFilter,
+    ) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| format!("Missing value for {}", arg))
+            };
+            match arg.as_str() {
+                "--address" => options.filter.addresses.push(value()?),
+                "--name-pattern" => {
+                    let pattern = value()?;
+                    let regex = Regex::new(&pattern)
+                        .map_err(|e| format!("Invalid --name-pattern {}: {}", pattern, e))?;
+                    options.filter.name_pattern = Some(regex);
+                }
+                "--service-uuid" => {
+                    let uuid = value()?;
+                    let uuid = uuid
+                        .parse()
+                        .map_err(|e| format!("Invalid --service-uuid: {}", e))?;
+                    options.filter.service = Some(uuid);
+                }
+                "--min-rssi" => {
+                    let rssi = value()?;
+                    let rssi = rssi
+                        .parse()
+                        .map_err(|_| format!("Invalid --min-rssi {}", rssi))?;
+                    options.filter.min_rssi = Some(rssi);
+                }
+                "--max-pathloss" => {
+                    let pathloss = value()?;
+                    let pathloss = pathloss
+                        .parse()
+                        .map_err(|_| format!("Invalid --max-pathloss {}", pathloss))?;
+                    options.pathloss = Some(pathloss);
+                }
+                "--count" => {
+                    let count = value()?;
+                    let count = count
+                        .parse()
+                        .ok()
+                        .filter(|n| *n > 0)
+                        .ok_or_else(|| format!("Invalid --count {}", count))?;
+                    options.count = Some(count);
+                }
+                "--adapter" => options.adapter = Some(value()?),
+                "--scan-timeout" => options.scan_timeout = Some(seconds(&arg, value()?)?),
+                "--resolve-timeout" => options.resolve_timeout = Some(seconds(&arg, value()?)?),
+                "--uinput" => options.uinput = true,
+                "--slots" => options.slots = Some(value()?),
+                "--claim" => options.claim = true,
+                "--mmc-calibration" => options.mmc_calibration = true,
+                "--low-battery" => {
+                    let percent = value()?;
+                    let percent = percent
+                        .parse()
+                        .ok()
+                        .filter(|p| *p <= 100)
+                        .ok_or_else(|| format!("Invalid --low-battery {}", percent))?;
+                    options.low_battery = Some(percent);
+                }
+                "--record" => options.record = Some(value()?),
+                "--replay" => options.replay = Some(value()?),
+                "--speed" => {
+                    let speed = value()?;
+                    // 太小的正数会让回放的等待时间超出 Duration 的范围
+                    options.speed = speed
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|s| *s == 0.0 || (s.is_finite() && *s >= MIN_SPEED))
+                        .ok_or_else(|| format!("Invalid --speed {}", speed))?;
+                }
+                "--verbose" => options.verbosity = options.verbosity.saturating_add(1),
+                "--quiet" => options.verbosity = options.verbosity.saturating_sub(1),
+                // -v, -vv, -q, -qq ...
+                flags if repeated(flags, 'v') => {
+                    options.verbosity = options.verbosity.saturating_add(flags.len() as i8 - 1)
+                }
+                flags if repeated(flags, 'q') => {
+                    options.verbosity = options.verbosity.saturating_sub(flags.len() as i8 - 1)
+                }
+                _ => return Err(format!("Unknown option {}", arg)),
+            }
+        }
+
+        // BlueZ 不接受同时设置 RSSI 和 Pathloss
+        if options.filter.min_rssi.is_some() && options.pathloss.is_some() {
+            return Err("--min-rssi and --max-pathloss cannot be combined".to_string());
+        }
+        if options.filter.addresses.is_empty() && options.filter.name_pattern.is_none() {
+            options.filter.addresses = filter.addresses;
+            options.filter.name_pattern = filter.name_pattern;
+        }
+        if options.filter.service.is_none() {
+            options.filter.service = filter.service;
+        }
+        if options.filter.min_rssi.is_none() {
+            options.filter.min_rssi = filter.min_rssi;
+        }
+        Ok(options)
+    }
+
+    /// The discovery filter for the stack: LE only, the service and signal
+    /// strength of the device filter
+    pub fn scan_filter(&self) -> ScanFilter {
+        ScanFilter {
+            services: self.filter.service.into_iter().collect(),
+            rssi: self.filter.min_rssi,
+            pathloss: self.pathloss,
+            ..Default::default()
+        }
+    }
+
+    /// Parse the process arguments, print the usage and exit on `--help`
+    /// or an error
+    pub fn from_env(filter: DeviceFilter) -> Options {
+        let args = env::args().skip(1).collect::<Vec<_>>();
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            println!("{}", USAGE);
+            process::exit(0);
+        }
+        match Options::parse(args, filter) {
+            Ok(options) => options,
+            Err(e) => {
+                eprintln!("{}\n\n{}", e, USAGE);
+                process::exit(2);
+            }
+        }
+    }
+}
+
+/// `-` followed by one or more `flag`
+fn repeated(arg: &str, flag: char) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && arg[1..].chars().all(|c| c == flag)
+}
+
+fn seconds(option: &str, value: String) -> Result<Duration, String> {
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|secs| secs.is_finite())
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .ok_or_else(|| format!("Invalid {} {}", option, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ble_uuid::BleUuid;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(
+            args.iter().map(|a| a.to_string()),
+            DeviceFilter::name("bell").unwrap(),
+        )
+    }
+
+    #[test]
+    fn parse_device_selection() {
+        let options = parse(&[
+            "--address",
+            "00:81:F9:DF:B0:40",
+            "--address",
+            "00:81:F9:DF:B0:41",
+            "--min-rssi",
+            "-80",
+            "--count",
+            "2",
+            "--adapter",
+            "hci1",
+            "--service-uuid",
+            "1809",
+            "--scan-timeout",
+            "2.5",
+            "--resolve-timeout",
+            "3",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            options.filter.addresses,
+            vec!["00:81:F9:DF:B0:40", "00:81:F9:DF:B0:41"]
+        );
+        assert_eq!(options.filter.min_rssi, Some(-80));
+        assert_eq!(options.count, Some(2));
+        assert_eq!(
+            options.scan_filter(),
+            ScanFilter {
+                services: vec![BleUuid::from_u16(0x1809)],
+                rssi: Some(-80),
+                ..Default::default()
+            }
+        );
+        assert!(options.filter.name_pattern.is_none());
+        assert_eq!(options.filter.service, Some(BleUuid::from_u16(0x1809)));
+        assert_eq!(options.adapter.as_deref(), Some("hci1"));
+        assert_eq!(options.scan_timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(options.resolve_timeout, Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn defaults_and_errors() {
+        let options = parse(&["--uinput", "--claim"]).unwrap();
+        assert!(options.uinput);
+        assert!(options.claim);
+        assert!(options.low_battery.is_none());
+        assert!(!options.mmc_calibration);
+        assert!(parse(&["--mmc-calibration"]).unwrap().mmc_calibration);
+        assert!(options.slots.is_none());
+        assert_eq!(options.filter.name_pattern.unwrap().as_str(), "bell");
+        assert_eq!(options.speed, 1.0);
+        assert_eq!(options.verbosity, 0);
+
+        assert_eq!(parse(&["-vv", "--verbose", "-q"]).unwrap().verbosity, 2);
+        assert_eq!(parse(&["--quiet"]).unwrap().verbosity, -1);
+
+        assert!(parse(&["--name-pattern", "("]).is_err());
+        assert!(parse(&["--service-uuid", "bell"]).is_err());
+        assert!(parse(&["--scan-timeout"]).is_err());
+        assert!(parse(&["--count", "0"]).is_err());
+        assert!(parse(&["--min-rssi", "strong"]).is_err());
+        assert_eq!(parse(&["--max-pathloss", "40"]).unwrap().pathloss, Some(40));
+        assert!(parse(&["--min-rssi", "-80", "--max-pathloss", "40"]).is_err());
+        assert!(parse(&["--resolve-timeout", "-1"]).is_err());
+        assert!(parse(&["--scan-timeout", "inf"]).is_err());
+        assert!(parse(&["--scan-timeout", "1e30"]).is_err());
+        assert!(parse(&["--scan-timeout", "NaN"]).is_err());
+        assert_eq!(parse(&["--speed", "0"]).unwrap().speed, 0.0);
+        assert!(parse(&["--speed", "1e-300"]).is_err());
+        assert!(parse(&["--speed", "-1"]).is_err());
+        assert!(parse(&["--speed", "inf"]).is_err());
+        assert_eq!(
+            parse(&["--low-battery", "15"]).unwrap().low_battery,
+            Some(15)
+        );
+        assert!(parse(&["--low-battery", "101"]).is_err());
+        assert!(parse(&["--bogus"]).is_err());
+    }
+}
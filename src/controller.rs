@@ -0,0 +1,316 @@
+//! Player slots for several controllers at once.
+//!
+//! Every controller keeps its player number, 1 to 4, across restarts. The
+//! slots are keyed by MAC address and saved as JSON:
+//!
+//! ```text
+//! {"slots":["E0:7D:EA:00:00:01",null,"E0:7D:EA:00:00:02",null]}
+//! ```
+
+use crate::backend::device_address;
+use crate::joystick::JoystickEvent;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub const PLAYER_SLOTS: usize = 4;
+
+/// How long claim mode lasts by default, see `ControllerManager::start_claim`
+pub const CLAIM_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Slots {
+    /// MAC address of player 1 to 4
+    slots: [Option<String>; PLAYER_SLOTS],
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PlayerEvent {
+    /// A controller got a slot, `player` counts from 1
+    Claimed {
+        player: usize,
+        address: String,
+    },
+    Input {
+        player: usize,
+        event: JoystickEvent,
+    },
+}
+
+/// Assigns the controllers to player slots.
+///
+/// A controller without a slot takes the first free one when it is first
+/// heard from. In claim mode it has to press Home for that instead, so the
+/// players can pick their numbers by the order they press Home in. Claim
+/// mode ends once every slot is taken or its time is up.
+#[derive(Debug, Default)]
+pub struct ControllerManager {
+    slots: Slots,
+    path: Option<PathBuf>,
+    /// End of claim mode, `None` outside of it
+    claim_until: Option<Instant>,
+    /// Slots from before claim mode, until the first controller claims one
+    previous: Option<Slots>,
+}
+
+impl ControllerManager {
+    /// Slots kept in memory only
+    pub fn new() -> ControllerManager {
+        ControllerManager::default()
+    }
+
+    /// Load the slots saved at `path`, starting empty if there is no such
+    /// file. Every assignment is written back.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<ControllerManager> {
+        let path = path.as_ref().to_path_buf();
+        let slots = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Slots::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(ControllerManager {
+            slots,
+            path: Some(path),
+            claim_until: None,
+            previous: None,
+        })
+    }
+
+    /// Forget every assignment and let the controllers claim their slot by
+    /// pressing Home, for up to `timeout`. The old assignments come back if
+    /// nobody claims a slot in time.
+    pub fn start_claim(&mut self, timeout: Duration) {
+        let slots = std::mem::take(&mut self.slots);
+        if self.previous.is_none() {
+            self.previous = Some(slots);
+        }
+        self.claim_until = Some(Instant::now() + timeout);
+    }
+
+    pub fn is_claiming(&self) -> bool {
+        self.claim_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Player number of the controller with MAC address `address`
+    pub fn player(&self, address: &str) -> Option<usize> {
+        self.slots
+            .slots
+            .iter()
+            .position(|slot| {
+                slot.as_deref()
+                    .is_some_and(|a| a.eq_ignore_ascii_case(address))
+            })
+            .map(|i| i + 1)
+    }
+
+    /// MAC addresses of player 1 to 4
+    pub fn slots(&self) -> &[Option<String>] {
+        &self.slots.slots
+    }
+
+    /// Tag an event with the player it belongs to, nothing for controllers
+    /// that don't have a slot (yet). The event that takes a slot comes
+    /// after the `Claimed` event.
+    pub fn handle(&mut self, event: JoystickEvent) -> Vec<PlayerEvent> {
+        // 电量和连接状态不是输入, 也不能占用位置
+        if !event.is_input() {
+            return vec![];
+        }
+        let address = device_address(event.object_path());
+        if let Some(player) = self.player(&address) {
+            return vec![PlayerEvent::Input { player, event }];
+        }
+        if self.claim_until.is_some() && !self.is_claiming() {
+            info!("Claiming player slots timed out");
+            self.claim_until = None;
+            // 一个都没认领就恢复原来的位置, 文件也还是原来的
+            if let Some(previous) = self.previous.take() {
+                self.slots = previous;
+                if let Some(player) = self.player(&address) {
+                    return vec![PlayerEvent::Input { player, event }];
+                }
+            }
+        }
+        // 认领模式下只有按 Home 键才分配位置
+        if self.claim_until.is_some() && !matches!(event, JoystickEvent::Home(_, true)) {
+            return vec![];
+        }
+
+        let free = match self.slots.slots.iter().position(|slot| slot.is_none()) {
+            Some(free) => free,
+            None => return vec![],
+        };
+        self.slots.slots[free] = Some(address.clone());
+        self.previous = None;
+        self.save();
+        if self.claim_until.is_some() && self.slots.slots.iter().all(|slot| slot.is_some()) {
+            info!("Every player slot is claimed");
+            self.claim_until = None;
+        }
+        vec![
+            PlayerEvent::Claimed {
+                player: free + 1,
+                address,
+            },
+            PlayerEvent::Input {
+                player: free + 1,
+                event,
+            },
+        ]
+    }
+
+    fn save(&self) {
+        if let Some(path) = &self.path {
+            if let Err(e) = write_slots(path, &self.slots) {
+                error!("Failed to save player slots to {:?}: {}", path, e);
+            }
+        }
+    }
+}
+
+fn write_slots(path: &Path, slots: &Slots) -> io::Result<()> {
+    // 先写临时文件再改名, 写到一半不会破坏原文件
+    let tmp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    serde_json::to_writer(&mut writer, slots)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    fs::rename(tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joystick::JoystickKeyEvent;
+    use std::env;
+    use std::process;
+
+    const BELL_1: &str = "/org/bluez/hci0/dev_E0_7D_EA_00_00_01";
+    const BELL_2: &str = "/org/bluez/hci0/dev_E0_7D_EA_00_00_02";
+
+    fn key(object_path: &str) -> JoystickEvent {
+        JoystickEvent::Key(object_path.to_string(), JoystickKeyEvent::default())
+    }
+
+    fn home(object_path: &str) -> JoystickEvent {
+        JoystickEvent::Home(object_path.to_string(), true)
+    }
+
+    #[test]
+    fn slots_are_kept_across_restarts() {
+        let path = env::temp_dir().join(format!("bell-slots-{}.json", process::id()));
+        fs::remove_file(&path).ok();
+
+        let mut manager = ControllerManager::open(&path).unwrap();
+        assert_eq!(
+            manager.handle(key(BELL_2)),
+            vec![
+                PlayerEvent::Claimed {
+                    player: 1,
+                    address: "E0:7D:EA:00:00:02".to_string()
+                },
+                PlayerEvent::Input {
+                    player: 1,
+                    event: key(BELL_2)
+                }
+            ]
+        );
+        manager.handle(key(BELL_1));
+
+        let mut manager = ControllerManager::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(manager.player("e0:7d:ea:00:00:01"), Some(2));
+        assert_eq!(
+            manager.handle(key(BELL_2)),
+            vec![PlayerEvent::Input {
+                player: 1,
+                event: key(BELL_2)
+            }]
+        );
+    }
+
+    #[test]
+    fn claim_slots_with_home() {
+        let mut manager = ControllerManager::new();
+        manager.handle(key(BELL_1));
+        assert_eq!(manager.player("E0:7D:EA:00:00:01"), Some(1));
+
+        manager.start_claim(CLAIM_TIMEOUT);
+        assert_eq!(manager.handle(key(BELL_1)), vec![]);
+        assert_eq!(
+            manager.handle(JoystickEvent::Home(BELL_1.to_string(), false)),
+            vec![]
+        );
+        assert_eq!(
+            manager.handle(home(BELL_2)),
+            vec![
+                PlayerEvent::Claimed {
+                    player: 1,
+                    address: "E0:7D:EA:00:00:02".to_string()
+                },
+                PlayerEvent::Input {
+                    player: 1,
+                    event: home(BELL_2)
+                }
+            ]
+        );
+        manager.handle(home(BELL_1));
+        assert_eq!(manager.player("E0:7D:EA:00:00:01"), Some(2));
+        assert_eq!(manager.slots()[2], None);
+        assert!(manager.is_claiming());
+    }
+
+    #[test]
+    fn claim_mode_ends() {
+        let mut manager = ControllerManager::new();
+        manager.start_claim(CLAIM_TIMEOUT);
+        for n in 1..=PLAYER_SLOTS {
+            manager.handle(home(&format!("/org/bluez/hci0/dev_E0_7D_EA_00_00_0{}", n)));
+        }
+        assert!(!manager.is_claiming());
+
+        // 超时之后回到普通模式, 任何按键都能拿到空位
+        let mut manager = ControllerManager::new();
+        manager.start_claim(Duration::from_secs(0));
+        assert!(!manager.is_claiming());
+        assert_eq!(manager.handle(key(BELL_1)).len(), 2);
+        assert_eq!(manager.player("E0:7D:EA:00:00:01"), Some(1));
+    }
+
+    #[test]
+    fn claim_keeps_old_slots_until_first_claim() {
+        let path = env::temp_dir().join(format!("bell-claim-{}.json", process::id()));
+        fs::remove_file(&path).ok();
+
+        let mut manager = ControllerManager::open(&path).unwrap();
+        manager.handle(key(BELL_1));
+        manager.start_claim(Duration::from_secs(0));
+        assert_eq!(manager.slots(), &[None, None, None, None]);
+        assert_eq!(
+            ControllerManager::open(&path)
+                .unwrap()
+                .player(&device_address(BELL_1)),
+            Some(1)
+        );
+
+        // 没人认领就超时了, 原来的位置还在
+        assert_eq!(
+            manager.handle(key(BELL_1)),
+            vec![PlayerEvent::Input {
+                player: 1,
+                event: key(BELL_1)
+            }]
+        );
+
+        manager.start_claim(CLAIM_TIMEOUT);
+        manager.handle(home(BELL_2));
+        let manager = ControllerManager::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(manager.player(&device_address(BELL_2)), Some(1));
+        assert_eq!(manager.player(&device_address(BELL_1)), None);
+    }
+}
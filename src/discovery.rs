@@ -0,0 +1,336 @@
+//! Picking our devices out of the scan results.
+
+use crate::backend::{Advertisement, BleBackend};
+use crate::ble_uuid::BleUuid;
+use crate::error::Error;
+use crate::logging::DISCOVERY;
+use log::debug;
+use regex::Regex;
+use std::time::{Duration, Instant};
+
+/// Which devices to use. Every criterion that is set has to match, an
+/// empty filter matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct DeviceFilter {
+    /// MAC addresses, case insensitive, any of them matches
+    pub addresses: Vec<String>,
+    pub name_pattern: Option<Regex>,
+    /// Advertised service. Devices whose backend does not report services
+    /// are not filtered out by it.
+    pub service: Option<BleUuid>,
+    /// Weakest signal in dBm. Devices without an RSSI are not filtered out
+    /// by it, for the same reason.
+    pub min_rssi: Option<i16>,
+}
+
+impl DeviceFilter {
+    /// Devices whose name matches `pattern`
+    pub fn name(pattern: &str) -> Result<DeviceFilter, regex::Error> {
+        Ok(DeviceFilter {
+            name_pattern: Some(Regex::new(pattern)?),
+            ..Default::default()
+        })
+    }
+
+    /// The device with MAC address `address`
+    pub fn address(address: &str) -> DeviceFilter {
+        DeviceFilter {
+            addresses: vec![address.to_string()],
+            ..Default::default()
+        }
+    }
+
+    pub fn matches(&self, advertisement: &Advertisement) -> bool {
+        if !self.addresses.is_empty()
+            && !self
+                .addresses
+                .iter()
+                .any(|a| advertisement.address.eq_ignore_ascii_case(a))
+        {
+            return false;
+        }
+        if let Some(pattern) = &self.name_pattern {
+            match &advertisement.name {
+                Some(name) if pattern.is_match(name) => {}
+                _ => return false,
+            }
+        }
+        if let Some(service) = &self.service {
+            if !advertisement.services.is_empty() && !advertisement.services.contains(service) {
+                return false;
+            }
+        }
+        if let (Some(min_rssi), Some(rssi)) = (self.min_rssi, advertisement.rssi) {
+            if rssi < min_rssi {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// How many devices can match at most, the number of addresses when
+    /// they are given
+    pub fn expected(&self) -> Option<usize> {
+        Some(self.addresses.len()).filter(|n| *n > 0)
+    }
+}
+
+/// A device as last seen, with when it was first and last seen
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    pub advertisement: Advertisement,
+    pub first_seen: Instant,
+    pub last_seen: Instant,
+}
+
+impl DiscoveredDevice {
+    /// Take the newer values, keeping the old name, RSSI and services when
+    /// `advertisement` does not have them
+    fn merge(&mut self, advertisement: Advertisement, now: Instant) {
+        let old = &mut self.advertisement;
+        old.name = advertisement.name.or_else(|| old.name.take());
+        old.rssi = advertisement.rssi.or(old.rssi);
+        for service in advertisement.services {
+            if !old.services.contains(&service) {
+                old.services.push(service);
+            }
+        }
+        old.paired = advertisement.paired;
+        old.trusted = advertisement.trusted;
+        self.first_seen = self.first_seen.min(now);
+        self.last_seen = self.last_seen.max(now);
+    }
+}
+
+/// Scan results and known devices merged by MAC address, in the order
+/// they were first seen. A device reported many times, e.g. on every RSSI
+/// change, or both by a scan and as paired, is in here once.
+#[derive(Clone, Debug, Default)]
+pub struct Discovered {
+    devices: Vec<DiscoveredDevice>,
+}
+
+impl Discovered {
+    pub fn add(&mut self, advertisement: Advertisement, now: Instant) {
+        match self.devices.iter_mut().find(|d| {
+            d.advertisement
+                .address
+                .eq_ignore_ascii_case(&advertisement.address)
+        }) {
+            Some(device) => device.merge(advertisement, now),
+            None => self.devices.push(DiscoveredDevice {
+                advertisement,
+                first_seen: now,
+                last_seen: now,
+            }),
+        }
+    }
+
+    /// The device with MAC address `address`, case insensitive
+    pub fn get(&self, address: &str) -> Option<&DiscoveredDevice> {
+        self.devices
+            .iter()
+            .find(|d| d.advertisement.address.eq_ignore_ascii_case(address))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &DiscoveredDevice> {
+        self.devices.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    /// Keep the first `len` devices only
+    pub fn truncate(&mut self, len: usize) {
+        self.devices.truncate(len);
+    }
+}
+
+/// Scan for the devices matching `filter`, for `timeout` at most. Returns
+/// as soon as `count` of them were seen, or when `count` is `None` all
+/// the addresses of the filter. Without either it scans for `timeout`.
+pub fn discover<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    filter: &DeviceFilter,
+    count: Option<usize>,
+    timeout: Duration,
+) -> Result<Discovered, Error> {
+    let count = count.or_else(|| filter.expected());
+    let mut discovered = Discovered::default();
+    let start = Instant::now();
+    backend.scan_until(timeout, &mut |device| {
+        match &device.name {
+            Some(name) => debug!(target: DISCOVERY, "{} {:?} {}", device.id, device.rssi, name),
+            None => debug!(target: DISCOVERY, "{} {:?}", device.id, device.rssi),
+        }
+        if filter.matches(device) {
+            discovered.add(device.clone(), Instant::now());
+        }
+        count.is_some_and(|count| discovered.len() >= count)
+    })?;
+    debug!(
+        target: DISCOVERY,
+        "Found {} devices in {:?}",
+        discovered.len(),
+        start.elapsed()
+    );
+    Ok(discovered)
+}
+
+/// Scan until the first device matching `filter` shows up, for `timeout`
+/// at most, then look among the devices the adapter knows
+pub fn find_device<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    filter: &DeviceFilter,
+    timeout: Duration,
+) -> Result<Option<Advertisement>, Error> {
+    if let Some(device) = discover(backend, filter, Some(1), timeout)?.iter().next() {
+        return Ok(Some(device.advertisement.clone()));
+    }
+    Ok(backend
+        .known_devices()?
+        .into_iter()
+        .find(|d| filter.matches(d)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::{SimBackend, SimDevice};
+
+    fn advertisement(address: &str, name: Option<&str>, services: &[&str]) -> Advertisement {
+        Advertisement {
+            id: address.to_string(),
+            address: address.to_string(),
+            name: name.map(|n| n.to_string()),
+            rssi: None,
+            services: services.iter().map(|s| s.parse().unwrap()).collect(),
+            paired: false,
+            trusted: false,
+        }
+    }
+
+    #[test]
+    fn filter_by_address_and_name() {
+        let bell = advertisement("E0:7D:EA:00:00:01", Some("bell-controller"), &[]);
+        let unnamed = advertisement("E0:7D:EA:00:00:02", None, &[]);
+
+        assert!(DeviceFilter::default().matches(&unnamed));
+        assert!(DeviceFilter::address("e0:7d:ea:00:00:01").matches(&bell));
+        assert!(!DeviceFilter::address("E0:7D:EA:00:00:02").matches(&bell));
+
+        let both = DeviceFilter {
+            addresses: vec!["E0:7D:EA:00:00:01".into(), "E0:7D:EA:00:00:02".into()],
+            ..Default::default()
+        };
+        assert!(both.matches(&bell) && both.matches(&unnamed));
+        assert_eq!(both.expected(), Some(2));
+
+        let filter = DeviceFilter::name("^bell").unwrap();
+        assert!(filter.matches(&bell));
+        assert!(!filter.matches(&unnamed));
+    }
+
+    #[test]
+    fn filter_by_service() {
+        let mmc = advertisement(
+            "00:81:F9:DF:B0:40",
+            Some("MMC"),
+            &["00001809-0000-1000-8000-00805f9b34fb"],
+        );
+        let unknown = advertisement("00:81:F9:DF:B0:41", Some("MMC"), &[]);
+
+        let mut filter = DeviceFilter {
+            service: Some(BleUuid::from_u16(0x1809)),
+            ..Default::default()
+        };
+        assert!(filter.matches(&mmc));
+        assert!(filter.matches(&unknown));
+
+        filter.service = Some("00001809-0000-1000-8000-00805F9B34FB".parse().unwrap());
+        assert!(filter.matches(&mmc));
+
+        filter.service = Some(BleUuid::from_u16(0x8850));
+        assert!(!filter.matches(&mmc));
+    }
+
+    #[test]
+    fn filter_by_rssi() {
+        let mut bell = advertisement("E0:7D:EA:00:00:01", Some("bell"), &[]);
+        let filter = DeviceFilter {
+            min_rssi: Some(-70),
+            ..Default::default()
+        };
+        assert!(filter.matches(&bell));
+        bell.rssi = Some(-70);
+        assert!(filter.matches(&bell));
+        bell.rssi = Some(-85);
+        assert!(!filter.matches(&bell));
+    }
+
+    #[test]
+    fn discover_stops_after_count() {
+        let mut sim = SimBackend::new();
+        sim.add_device(SimDevice::new("E0:7D:EA:00:00:01", "bell-1"))
+            .add_device(SimDevice::new("00:81:F9:DF:B0:40", "MMC"))
+            .add_device(SimDevice::new("E0:7D:EA:00:00:02", "bell-2"));
+        let timeout = Duration::from_secs(1);
+
+        let bells = DeviceFilter::name("bell").unwrap();
+        assert_eq!(discover(&mut sim, &bells, None, timeout).unwrap().len(), 2);
+        let first = discover(&mut sim, &bells, Some(1), timeout).unwrap();
+        assert_eq!(first.len(), 1);
+        assert!(first.get("E0:7D:EA:00:00:01").is_some());
+        assert_eq!(sim.scanned(), 4);
+
+        // 只给了地址时找到这个设备就停
+        let mmc = DeviceFilter::address("00:81:f9:df:b0:40");
+        assert_eq!(discover(&mut sim, &mmc, None, timeout).unwrap().len(), 1);
+        assert_eq!(sim.scanned(), 6);
+    }
+
+    #[test]
+    fn merge_by_address() {
+        let start = Instant::now();
+        let later = start + Duration::from_secs(5);
+        let mut discovered = Discovered::default();
+
+        let mut bell = advertisement("E0:7D:EA:00:00:01", Some("bell-controller"), &["8850"]);
+        bell.rssi = Some(-60);
+        discovered.add(bell.clone(), start);
+        discovered.add(advertisement("00:81:F9:DF:B0:40", Some("MMC"), &[]), start);
+
+        bell.rssi = Some(-48);
+        discovered.add(bell.clone(), later);
+        // 已配对列表里的同一个设备, 没有 RSSI 和名字
+        let mut paired = advertisement("e0:7d:ea:00:00:01", None, &["180f"]);
+        paired.paired = true;
+        paired.trusted = true;
+        discovered.add(paired, later);
+
+        assert_eq!(discovered.len(), 2);
+        let device = discovered.get("E0:7D:EA:00:00:01").unwrap();
+        assert_eq!(
+            device.advertisement.name.as_deref(),
+            Some("bell-controller")
+        );
+        assert_eq!(device.advertisement.rssi, Some(-48));
+        assert_eq!(
+            device.advertisement.services,
+            vec![BleUuid::from_u16(0x8850), BleUuid::from_u16(0x180f)]
+        );
+        assert!(device.advertisement.paired && device.advertisement.trusted);
+        assert_eq!((device.first_seen, device.last_seen), (start, later));
+
+        let addresses = discovered
+            .iter()
+            .map(|d| d.advertisement.address.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(addresses, vec!["E0:7D:EA:00:00:01", "00:81:F9:DF:B0:40"]);
+    }
+}
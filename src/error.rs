@@ -0,0 +1,94 @@
+//! The error type of every fallible function in the crate.
+
+use crate::ble_uuid::{BleUuid, ParseUuidError};
+use crate::joystick::DecodeError;
+use crate::thermometer::MeasurementError;
+use std::io;
+use std::time::Duration;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    /// No adapter of that name, "default" when none was asked for
+    #[error("bluetooth adapter {0} not found")]
+    AdapterNotFound(String),
+    #[error("device {0} not found")]
+    DeviceNotFound(String),
+    #[error("service {} not found on {device}", .service.to_short_string())]
+    ServiceNotFound { device: String, service: BleUuid },
+    #[error(
+        "characteristic {}/{} not found on {device}",
+        .service.to_short_string(),
+        .characteristic.to_short_string()
+    )]
+    CharacteristicNotFound {
+        device: String,
+        service: BleUuid,
+        characteristic: BleUuid,
+    },
+    #[error("device {0} not connected")]
+    NotConnected(String),
+    #[error("device {0} disconnected")]
+    Disconnected(String),
+    /// A BlueZ call over D-Bus failed
+    #[error("D-Bus error: {0}")]
+    Dbus(String),
+    #[error("{operation} on {device} timed out after {timeout:?}")]
+    Timeout {
+        device: String,
+        operation: &'static str,
+        timeout: Duration,
+    },
+    /// A notification or characteristic value that could not be decoded
+    #[error("invalid value: {0}")]
+    Decode(Box<dyn std::error::Error + Send + Sync>),
+    /// The backend can't deliver events any more, its event channel or
+    /// D-Bus connection is gone
+    #[error("{0} closed")]
+    Closed(String),
+    /// Any other error of the BLE stack, or an operation it does not
+    /// support
+    #[error("{0}")]
+    Backend(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl Error {
+    /// Whether retrying is pointless, the process should exit and be
+    /// restarted instead
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            Error::AdapterNotFound(_) | Error::Closed(_) => true,
+            Error::Io(e) => !matches!(
+                e.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<DecodeError> for Error {
+    fn from(e: DecodeError) -> Error {
+        Error::Decode(Box::new(e))
+    }
+}
+
+impl From<ParseUuidError> for Error {
+    fn from(e: ParseUuidError) -> Error {
+        Error::Decode(Box::new(e))
+    }
+}
+
+impl From<MeasurementError> for Error {
+    fn from(e: MeasurementError) -> Error {
+        Error::Decode(Box::new(e))
+    }
+}
+
+/// blurz returns every D-Bus failure as a boxed error
+#[cfg(feature = "blurz")]
+pub(crate) fn dbus(e: Box<dyn std::error::Error>) -> Error {
+    Error::Dbus(e.to_string())
+}
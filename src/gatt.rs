@@ -0,0 +1,210 @@
+//! GATT discovery helpers on top of blurz.
+
+use blurz::bluetooth_device::BluetoothDevice;
+use blurz::bluetooth_gatt_characteristic::BluetoothGATTCharacteristic;
+use blurz::bluetooth_gatt_descriptor::BluetoothGATTDescriptor;
+use blurz::bluetooth_gatt_service::BluetoothGATTService;
+use blurz::bluetooth_session::BluetoothSession;
+
+pub use crate::backend::device_path;
+use crate::ble_uuid::BleUuid;
+use crate::error::{dbus, Error};
+use crate::gatt_dump::{CharacteristicDump, DescriptorDump, GattDump, ServiceDump};
+use crate::logging::GATT;
+use crate::sig::{characteristic_name, decode_descriptor, descriptor_name, service_name};
+use log::{debug, info, warn};
+
+/// "0x180f Battery", or just the number if it has no SIG name
+fn named(uuid: BleUuid, name: Option<&str>) -> String {
+    let number = match uuid.to_u32() {
+        Some(_) => format!("0x{}", uuid.to_short_string()),
+        None => uuid.to_string(),
+    };
+    match name {
+        Some(name) => format!("{} {}", number, name),
+        None => number,
+    }
+}
+
+/// List characteristics in service
+pub fn list_characteritics(
+    service: &BluetoothGATTService,
+    session: &BluetoothSession,
+) -> Result<(), Error> {
+    // list characteristics
+    let characteristics = service.get_gatt_characteristics().map_err(dbus)?;
+    for characteristic_path in characteristics {
+        let characteristic = BluetoothGATTCharacteristic::new(session, characteristic_path);
+        let uuid: BleUuid = characteristic.get_uuid().map_err(dbus)?.parse()?;
+        let flags = characteristic.get_flags().map_err(dbus)?;
+
+        info!(
+            target: GATT,
+            " Characteristic UUID: {}, Assigned Number: {} Flags: {:?}",
+            uuid,
+            named(uuid, characteristic_name(uuid)),
+            flags
+        );
+
+        list_descriptors(&characteristic, session)?;
+    }
+    Ok(())
+}
+
+pub fn get_service<'a>(
+    service_uuid: BleUuid,
+    device: &BluetoothDevice<'a>,
+    session: &'a BluetoothSession,
+) -> Result<BluetoothGATTService<'a>, Error> {
+    let services_list = device.get_gatt_services().map_err(dbus)?;
+
+    for service_path in services_list {
+        let service = BluetoothGATTService::new(session, service_path.to_string());
+        let uuid: BleUuid = service.get_uuid().map_err(dbus)?.parse()?;
+
+        debug!(target: GATT, "Service UUID: {}", uuid);
+        if uuid == service_uuid {
+            return Ok(service);
+        }
+    }
+    Err(Error::ServiceNotFound {
+        device: device.get_id(),
+        service: service_uuid,
+    })
+}
+
+pub fn get_characteritic<'a>(
+    characteristic_uuid: BleUuid,
+    service: &BluetoothGATTService<'a>,
+    session: &'a BluetoothSession,
+) -> Result<BluetoothGATTCharacteristic<'a>, Error> {
+    // list characteristics
+    let characteristics = service.get_gatt_characteristics().map_err(dbus)?;
+    for characteristic_path in characteristics {
+        let characteristic = BluetoothGATTCharacteristic::new(session, characteristic_path);
+        let uuid: BleUuid = characteristic.get_uuid().map_err(dbus)?.parse()?;
+        let flags = characteristic.get_flags().map_err(dbus)?;
+
+        debug!(
+            target: GATT,
+            " Characteristic UUID: {} Flags: {:?}",
+            uuid, flags
+        );
+
+        if uuid == characteristic_uuid {
+            return Ok(characteristic);
+        }
+    }
+    let service_path = service.get_id();
+    Err(Error::CharacteristicNotFound {
+        device: device_path(&service_path).to_string(),
+        service: service.get_uuid().map_err(dbus)?.parse()?,
+        characteristic: characteristic_uuid,
+    })
+}
+
+/// List descriptors in characteristic. A descriptor that cannot be read
+/// is listed with its error.
+pub fn list_descriptors(
+    characteristic: &BluetoothGATTCharacteristic,
+    session: &BluetoothSession,
+) -> Result<(), Error> {
+    let descriptors = characteristic.get_gatt_descriptors().map_err(dbus)?;
+    for descriptor_path in descriptors {
+        let descriptor = BluetoothGATTDescriptor::new(session, descriptor_path);
+        if let Err(e) = list_descriptor(&descriptor) {
+            warn!(
+                target: GATT,
+                "    Descriptor {}: {}",
+                descriptor.get_id(),
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+fn list_descriptor(descriptor: &BluetoothGATTDescriptor) -> Result<(), Error> {
+    let uuid: BleUuid = descriptor.get_uuid().map_err(dbus)?.parse()?;
+    let value = descriptor.read_value(None).map_err(dbus)?;
+    info!(
+        target: GATT,
+        "    Descriptor UUID: {}, Assigned Number: {} Read Value: {}",
+        uuid,
+        named(uuid, descriptor_name(uuid)),
+        decode_descriptor(uuid, &value)
+    );
+    Ok(())
+}
+
+/// Print services, characteristics and descriptors of a connected device
+pub fn explore_device(device: &BluetoothDevice, session: &BluetoothSession) -> Result<(), Error> {
+    // list services
+    let services_list = device.get_gatt_services().map_err(dbus)?;
+
+    for service_path in services_list {
+        let service = BluetoothGATTService::new(session, service_path.to_string());
+        let uuid: BleUuid = service.get_uuid().map_err(dbus)?.parse()?;
+
+        info!(
+            target: GATT,
+            "Service UUID: {} Assigned Number: {}",
+            uuid,
+            named(uuid, service_name(uuid))
+        );
+
+        list_characteritics(&service, session)?;
+    }
+    Ok(())
+}
+
+/// Services, characteristics and descriptors of a connected device with
+/// their values, see `gatt_dump`. Values that cannot be read are left out.
+pub fn dump_device(
+    device: &BluetoothDevice,
+    session: &BluetoothSession,
+) -> Result<GattDump, Error> {
+    let mut services = vec![];
+    for service_path in device.get_gatt_services().map_err(dbus)? {
+        let service = BluetoothGATTService::new(session, service_path);
+        let mut characteristics = vec![];
+        for characteristic_path in service.get_gatt_characteristics().map_err(dbus)? {
+            let c = BluetoothGATTCharacteristic::new(session, characteristic_path);
+            let flags = c.get_flags().map_err(dbus)?;
+            // 没有 read 权限的特征读了也是报错, 直接跳过
+            let value = if flags.iter().any(|f| f == "read") {
+                c.read_value(None)
+                    .map_err(|e| debug!(target: GATT, "Failed to read {}: {}", c.get_id(), e))
+                    .ok()
+            } else {
+                None
+            };
+            let mut descriptors = vec![];
+            for descriptor_path in c.get_gatt_descriptors().map_err(dbus)? {
+                let d = BluetoothGATTDescriptor::new(session, descriptor_path);
+                descriptors.push(DescriptorDump {
+                    uuid: d.get_uuid().map_err(dbus)?.parse()?,
+                    value: d
+                        .read_value(None)
+                        .map_err(|e| debug!(target: GATT, "Failed to read {}: {}", d.get_id(), e))
+                        .ok(),
+                });
+            }
+            characteristics.push(CharacteristicDump {
+                uuid: c.get_uuid().map_err(dbus)?.parse()?,
+                flags,
+                value,
+                descriptors,
+            });
+        }
+        services.push(ServiceDump {
+            uuid: service.get_uuid().map_err(dbus)?.parse()?,
+            characteristics,
+        });
+    }
+    Ok(GattDump {
+        address: device.get_address().map_err(dbus)?,
+        name: device.get_name().ok(),
+        services,
+    })
+}
@@ -0,0 +1,286 @@
+//! The GATT database of a device as JSON, and the difference of two such
+//! dumps, to check a firmware revision against a known good layout.
+//!
+//! ```text
+//! {"address":"E0:7D:EA:00:00:01","name":"bell-controller","services":[
+//!   {"uuid":"0000180a-0000-1000-8000-00805f9b34fb","characteristics":[
+//!     {"uuid":"00002a26-0000-1000-8000-00805f9b34fb","flags":["read"],
+//!      "value":"312e30","descriptors":[]}]}]}
+//! ```
+//!
+//! Values are hex strings, `null` when they could not be read.
+
+use crate::ble_uuid::BleUuid;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GattDump {
+    pub address: String,
+    pub name: Option<String>,
+    pub services: Vec<ServiceDump>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceDump {
+    pub uuid: BleUuid,
+    pub characteristics: Vec<CharacteristicDump>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CharacteristicDump {
+    pub uuid: BleUuid,
+    pub flags: Vec<String>,
+    /// Only read when the flags allow it
+    #[serde(with = "hex")]
+    pub value: Option<Vec<u8>>,
+    pub descriptors: Vec<DescriptorDump>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DescriptorDump {
+    pub uuid: BleUuid,
+    #[serde(with = "hex")]
+    pub value: Option<Vec<u8>>,
+}
+
+/// One difference between two dumps. `path` is the assigned numbers of
+/// service, characteristic and descriptor, e.g. "180a/2a26".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GattChange {
+    Added(String),
+    Removed(String),
+    Changed {
+        path: String,
+        what: &'static str,
+        old: String,
+        new: String,
+    },
+}
+
+impl fmt::Display for GattChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GattChange::Added(path) => write!(f, "+ {}", path),
+            GattChange::Removed(path) => write!(f, "- {}", path),
+            GattChange::Changed {
+                path,
+                what,
+                old,
+                new,
+            } => write!(f, "~ {} {}: {} -> {}", path, what, old, new),
+        }
+    }
+}
+
+/// What changed from `old` to `new`. Services, characteristics and
+/// descriptors are matched by UUID, the address and name are ignored.
+pub fn diff(old: &GattDump, new: &GattDump) -> Vec<GattChange> {
+    let mut changes = vec![];
+    for (old, new) in pair_up(&old.services, &new.services, |s| s.uuid) {
+        let path = short(old.or(new).map(|s| s.uuid));
+        match (old, new) {
+            (Some(old), Some(new)) => diff_service(&path, old, new, &mut changes),
+            (Some(_), None) => changes.push(GattChange::Removed(path)),
+            (None, _) => changes.push(GattChange::Added(path)),
+        }
+    }
+    changes
+}
+
+fn diff_service(path: &str, old: &ServiceDump, new: &ServiceDump, changes: &mut Vec<GattChange>) {
+    for (old, new) in pair_up(&old.characteristics, &new.characteristics, |c| c.uuid) {
+        let path = format!("{}/{}", path, short(old.or(new).map(|c| c.uuid)));
+        let (old, new) = match (old, new) {
+            (Some(old), Some(new)) => (old, new),
+            (Some(_), None) => {
+                changes.push(GattChange::Removed(path));
+                continue;
+            }
+            (None, _) => {
+                changes.push(GattChange::Added(path));
+                continue;
+            }
+        };
+        if old.flags != new.flags {
+            changes.push(GattChange::Changed {
+                path: path.clone(),
+                what: "flags",
+                old: format!("{:?}", old.flags),
+                new: format!("{:?}", new.flags),
+            });
+        }
+        diff_value(&path, &old.value, &new.value, changes);
+
+        for (old, new) in pair_up(&old.descriptors, &new.descriptors, |d| d.uuid) {
+            let path = format!("{}/{}", path, short(old.or(new).map(|d| d.uuid)));
+            match (old, new) {
+                (Some(old), Some(new)) => diff_value(&path, &old.value, &new.value, changes),
+                (Some(_), None) => changes.push(GattChange::Removed(path)),
+                (None, _) => changes.push(GattChange::Added(path)),
+            }
+        }
+    }
+}
+
+fn diff_value(
+    path: &str,
+    old: &Option<Vec<u8>>,
+    new: &Option<Vec<u8>>,
+    changes: &mut Vec<GattChange>,
+) {
+    if old != new {
+        changes.push(GattChange::Changed {
+            path: path.to_string(),
+            what: "value",
+            old: hex::format(old),
+            new: hex::format(new),
+        });
+    }
+}
+
+fn short(uuid: Option<BleUuid>) -> String {
+    uuid.map(BleUuid::to_short_string).unwrap_or_default()
+}
+
+/// Match the items of `old` and `new` with the same UUID, in the order of
+/// `old` followed by the ones only in `new`
+fn pair_up<'a, T>(
+    old: &'a [T],
+    new: &'a [T],
+    uuid: fn(&T) -> BleUuid,
+) -> Vec<(Option<&'a T>, Option<&'a T>)> {
+    let mut used = vec![false; new.len()];
+    let mut pairs = vec![];
+    for o in old {
+        // 同一个 UUID 出现多次时按顺序一一对应
+        let matched = (0..new.len()).find(|i| !used[*i] && uuid(&new[*i]) == uuid(o));
+        if let Some(i) = matched {
+            used[i] = true;
+        }
+        pairs.push((Some(o), matched.map(|i| &new[i])));
+    }
+    for (i, n) in new.iter().enumerate() {
+        if !used[i] {
+            pairs.push((None, Some(n)));
+        }
+    }
+    pairs
+}
+
+/// Values as hex strings
+mod hex {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn format(value: &Option<Vec<u8>>) -> String {
+        match value {
+            Some(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+            None => "null".to_string(),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(value: &Option<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(_) => s.serialize_str(&format(value)),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Vec<u8>>, D::Error> {
+        let value = match Option::<String>::deserialize(d)? {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if value.len() % 2 != 0 {
+            return Err(de::Error::custom(format!("odd length hex {}", value)));
+        }
+        (0..value.len())
+            .step_by(2)
+            .map(|i| {
+                value
+                    .get(i..i + 2)
+                    .and_then(|b| u8::from_str_radix(b, 16).ok())
+                    .ok_or_else(|| de::Error::custom(format!("invalid hex {}", value)))
+            })
+            .collect::<Result<Vec<u8>, D::Error>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn characteristic(uuid: u16, flags: &[&str], value: Option<&[u8]>) -> CharacteristicDump {
+        CharacteristicDump {
+            uuid: BleUuid::from_u16(uuid),
+            flags: flags.iter().map(|f| f.to_string()).collect(),
+            value: value.map(|v| v.to_vec()),
+            descriptors: vec![],
+        }
+    }
+
+    fn bell(firmware: &[u8]) -> GattDump {
+        GattDump {
+            address: "E0:7D:EA:00:00:01".to_string(),
+            name: Some("bell-controller".to_string()),
+            services: vec![
+                ServiceDump {
+                    uuid: BleUuid::from_u16(0x180a),
+                    characteristics: vec![characteristic(0x2a26, &["read"], Some(firmware))],
+                },
+                ServiceDump {
+                    uuid: BleUuid::from_u16(0x8850),
+                    characteristics: vec![CharacteristicDump {
+                        descriptors: vec![DescriptorDump {
+                            uuid: BleUuid::from_u16(0x2902),
+                            value: Some(vec![0, 0]),
+                        }],
+                        ..characteristic(0x885a, &["notify"], None)
+                    }],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let dump = bell(b"1.0");
+        let json = serde_json::to_string(&dump).unwrap();
+        assert!(json.contains(r#""value":"312e30""#));
+        assert!(json.contains(r#""value":null"#));
+        assert_eq!(serde_json::from_str::<GattDump>(&json).unwrap(), dump);
+
+        let invalid = json.replace("312e30", "312e3");
+        assert!(serde_json::from_str::<GattDump>(&invalid).is_err());
+    }
+
+    #[test]
+    fn diff_dumps() {
+        let old = bell(b"1.0");
+        assert!(diff(&old, &old).is_empty());
+
+        let mut new = bell(b"1.1");
+        new.services[1].characteristics[0]
+            .flags
+            .push("read".to_string());
+        new.services[1].characteristics[0].descriptors.clear();
+        new.services.push(ServiceDump {
+            uuid: BleUuid::from_u16(0x180f),
+            characteristics: vec![],
+        });
+        let changes = diff(&old, &new)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                "~ 180a/2a26 value: 312e30 -> 312e31",
+                r#"~ 8850/885a flags: ["notify"] -> ["notify", "read"]"#,
+                "- 8850/885a/2902",
+                "+ 180f",
+            ]
+        );
+    }
+}
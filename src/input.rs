@@ -0,0 +1,299 @@
+//! Press/release edge events on top of the joystick state snapshots.
+
+use crate::backend::device_path;
+use crate::joystick::{Hat, JoystickEvent, JoystickKeyEvent};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Button {
+    A,
+    B,
+    C,
+    D,
+    I,
+    II,
+    L1,
+    R1,
+    /// L2 pressed all the way down
+    L2,
+    /// R2 pressed all the way down
+    R2,
+    Home,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Axis {
+    RlX,
+    RlY,
+    RrX,
+    RrY,
+    L2,
+    R2,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InputEvent {
+    ButtonDown(Button),
+    ButtonUp(Button),
+    AxisChanged(Axis, u8),
+    HatChanged(Hat),
+}
+
+#[derive(Clone, Debug, Default)]
+struct DeviceState {
+    key: JoystickKeyEvent,
+    home: bool,
+    /// A key report arrived, so the knob positions are known
+    seeded: bool,
+}
+
+impl DeviceState {
+    fn buttons(&self) -> [(Button, bool); 11] {
+        let key = &self.key;
+        [
+            (Button::A, key.a),
+            (Button::B, key.b),
+            (Button::C, key.c),
+            (Button::D, key.d),
+            (Button::I, key.i),
+            (Button::II, key.ii),
+            (Button::L1, key.l1),
+            (Button::R1, key.r1),
+            (Button::L2, key.l2.1),
+            (Button::R2, key.r2.1),
+            (Button::Home, self.home),
+        ]
+    }
+
+    fn axes(&self) -> [(Axis, u8); 6] {
+        let key = &self.key;
+        [
+            (Axis::RlX, key.rl.0),
+            (Axis::RlY, key.rl.1),
+            (Axis::RrX, key.rr.0),
+            (Axis::RrY, key.rr.1),
+            (Axis::L2, key.l2.0),
+            (Axis::R2, key.r2.0),
+        ]
+    }
+
+    /// State after every button and trigger is let go, the knobs keep
+    /// their last position
+    fn released(&self) -> DeviceState {
+        DeviceState {
+            key: JoystickKeyEvent {
+                rl: self.key.rl,
+                rr: self.key.rr,
+                ..Default::default()
+            },
+            home: false,
+            seeded: self.seeded,
+        }
+    }
+
+    fn diff(&self, new: &DeviceState) -> Vec<InputEvent> {
+        let mut events = vec![];
+
+        for ((button, old), (_, new)) in self.buttons().iter().zip(new.buttons().iter()) {
+            if !old && *new {
+                events.push(InputEvent::ButtonDown(*button));
+            } else if *old && !new {
+                events.push(InputEvent::ButtonUp(*button));
+            }
+        }
+
+        for ((axis, old), (_, new)) in self.axes().iter().zip(new.axes().iter()) {
+            if old != new {
+                events.push(InputEvent::AxisChanged(*axis, *new));
+            }
+        }
+
+        if self.key.hat != new.key.hat {
+            events.push(InputEvent::HatChanged(new.key.hat));
+        }
+
+        events
+    }
+}
+
+/// Turns joystick snapshots into edge events, one state per device
+#[derive(Debug, Default)]
+pub struct InputTracker {
+    devices: HashMap<String, DeviceState>,
+}
+
+impl InputTracker {
+    pub fn new() -> InputTracker {
+        InputTracker::default()
+    }
+
+    /// Compare a snapshot with the last one of the same device, returns
+    /// the device and its edge events
+    pub fn update(&mut self, event: &JoystickEvent) -> (String, Vec<InputEvent>) {
+        let device = device_path(event.object_path()).to_string();
+        if !event.is_input() {
+            return (device, vec![]);
+        }
+        let state = self.devices.entry(device.clone()).or_default();
+
+        let mut new = state.clone();
+        match event {
+            JoystickEvent::Key(_, key) => {
+                new.key = key.clone();
+                new.seeded = true;
+            }
+            JoystickEvent::Home(_, down) => new.home = *down,
+            _ => {}
+        }
+
+        let mut events = state.diff(&new);
+        // 第一个报告之前不知道旋钮的位置, 不管有没有变化都把它报出去
+        if !state.seeded && new.seeded {
+            for (axis, value) in new.axes().iter().take(4) {
+                let event = InputEvent::AxisChanged(*axis, *value);
+                if !events.contains(&event) {
+                    events.push(event);
+                }
+            }
+        }
+        *state = new;
+        (device, events)
+    }
+
+    /// Forget a device, releasing everything it still holds
+    pub fn disconnect(&mut self, object_path: &str) -> Vec<InputEvent> {
+        match self.devices.remove(device_path(object_path)) {
+            Some(state) => state.diff(&state.released()),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DEVICE: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55";
+    const CHAR: &str = "/org/bluez/hci0/dev_00_11_22_33_44_55/service000c/char000d";
+
+    fn key(key: JoystickKeyEvent) -> JoystickEvent {
+        JoystickEvent::Key(CHAR.to_string(), key)
+    }
+
+    /// A tracker that already got the first report, the knobs at 0
+    fn seeded() -> InputTracker {
+        let mut tracker = InputTracker::new();
+        tracker.update(&key(JoystickKeyEvent::default()));
+        tracker
+    }
+
+    #[test]
+    fn emits_press_and_release_once() {
+        let mut tracker = seeded();
+        let pressed = JoystickKeyEvent {
+            a: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            tracker.update(&key(pressed.clone())),
+            (DEVICE.to_string(), vec![InputEvent::ButtonDown(Button::A)])
+        );
+        assert_eq!(tracker.update(&key(pressed)).1, vec![]);
+        assert_eq!(
+            tracker.update(&key(JoystickKeyEvent::default())).1,
+            vec![InputEvent::ButtonUp(Button::A)]
+        );
+    }
+
+    #[test]
+    fn emits_axis_and_hat_changes() {
+        let mut tracker = seeded();
+        let (_, events) = tracker.update(&key(JoystickKeyEvent {
+            hat: Hat::DownLeft,
+            l2: (0x40, false),
+            ..Default::default()
+        }));
+
+        assert_eq!(
+            events,
+            vec![
+                InputEvent::AxisChanged(Axis::L2, 0x40),
+                InputEvent::HatChanged(Hat::DownLeft),
+            ]
+        );
+    }
+
+    #[test]
+    fn first_report_seeds_the_knobs() {
+        let mut tracker = InputTracker::new();
+        let centered = JoystickKeyEvent {
+            rl: (0x80, 0x80),
+            rr: (0x80, 0x80),
+            ..Default::default()
+        };
+        tracker.update(&JoystickEvent::Home(CHAR.to_string(), true));
+        assert_eq!(
+            tracker.update(&key(centered.clone())).1,
+            vec![
+                InputEvent::AxisChanged(Axis::RlX, 0x80),
+                InputEvent::AxisChanged(Axis::RlY, 0x80),
+                InputEvent::AxisChanged(Axis::RrX, 0x80),
+                InputEvent::AxisChanged(Axis::RrY, 0x80),
+            ]
+        );
+        assert_eq!(tracker.update(&key(centered.clone())).1, vec![]);
+        assert_eq!(
+            tracker
+                .update(&key(JoystickKeyEvent {
+                    rl: (0x90, 0x80),
+                    ..centered
+                }))
+                .1,
+            vec![InputEvent::AxisChanged(Axis::RlX, 0x90)]
+        );
+    }
+
+    #[test]
+    fn events_are_attributed_to_their_device() {
+        const OTHER: &str = "/org/bluez/hci0/dev_00_11_22_33_44_66";
+        let mut tracker = InputTracker::new();
+        tracker.update(&JoystickEvent::Home(CHAR.to_string(), true));
+        assert_eq!(
+            tracker.update(&JoystickEvent::Home(OTHER.to_string(), true)),
+            (
+                OTHER.to_string(),
+                vec![InputEvent::ButtonDown(Button::Home)]
+            )
+        );
+        assert_eq!(
+            tracker.update(&JoystickEvent::Home(CHAR.to_string(), false)),
+            (DEVICE.to_string(), vec![InputEvent::ButtonUp(Button::Home)])
+        );
+    }
+
+    #[test]
+    fn disconnect_releases_held_inputs() {
+        let mut tracker = InputTracker::new();
+        tracker.update(&key(JoystickKeyEvent {
+            hat: Hat::Up,
+            b: true,
+            r2: (0xff, true),
+            rl: (0x80, 0x80),
+            ..Default::default()
+        }));
+        tracker.update(&JoystickEvent::Home(CHAR.to_string(), true));
+
+        assert_eq!(
+            tracker.disconnect(DEVICE),
+            vec![
+                InputEvent::ButtonUp(Button::B),
+                InputEvent::ButtonUp(Button::R2),
+                InputEvent::ButtonUp(Button::Home),
+                InputEvent::AxisChanged(Axis::R2, 0),
+                InputEvent::HatChanged(Hat::Centered),
+            ]
+        );
+        assert_eq!(tracker.disconnect(DEVICE), vec![]);
+    }
+}
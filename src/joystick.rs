@@ -0,0 +1,593 @@
+//! Bell joystick discovery, connection and key report decoding.
+
+use crate::backend::{connect_and_resolve, Advertisement, BackendEvent, BleBackend};
+use crate::battery::{parse_battery_level, BATTERY_LEVEL_UUID};
+use crate::ble_uuid::BleUuid;
+use crate::discovery::{discover, DeviceFilter, Discovered};
+use crate::error::Error;
+use crate::logging::{DECODE, DISCOVERY, EVENTS};
+use log::{debug, info, trace, warn};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const BELL_SERVICE_UUID: BleUuid = BleUuid::from_u16(0x8850);
+pub const BELL_CHAR_UUID: BleUuid = BleUuid::from_u16(0x885a);
+
+/// D-pad position decoded from byte 8
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Hat {
+    #[default]
+    Centered,
+    Up,
+    UpRight,
+    Right,
+    DownRight,
+    Down,
+    DownLeft,
+    Left,
+    UpLeft,
+}
+
+impl Hat {
+    /// Decode the D-pad byte, `None` if the value is out of range
+    pub fn from_byte(value: u8) -> Option<Hat> {
+        let hat = match value {
+            JS_CENTERED => Hat::Centered,
+            JS_UP => Hat::Up,
+            JS_UP_RIGHT => Hat::UpRight,
+            JS_RIGHT => Hat::Right,
+            JS_DOWN_RIGHT => Hat::DownRight,
+            JS_DOWN => Hat::Down,
+            JS_DOWN_LEFT => Hat::DownLeft,
+            JS_LEFT => Hat::Left,
+            JS_UP_LEFT => Hat::UpLeft,
+            _ => return None,
+        };
+        Some(hat)
+    }
+
+    /// Horizontal component, -1 for left, 1 for right
+    pub fn x(self) -> i8 {
+        match self {
+            Hat::UpLeft | Hat::Left | Hat::DownLeft => -1,
+            Hat::UpRight | Hat::Right | Hat::DownRight => 1,
+            _ => 0,
+        }
+    }
+
+    /// Vertical component, -1 for up, 1 for down
+    pub fn y(self) -> i8 {
+        match self {
+            Hat::UpLeft | Hat::Up | Hat::UpRight => -1,
+            Hat::DownLeft | Hat::Down | Hat::DownRight => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn is_up(self) -> bool {
+        self.y() < 0
+    }
+
+    pub fn is_down(self) -> bool {
+        self.y() > 0
+    }
+
+    pub fn is_left(self) -> bool {
+        self.x() < 0
+    }
+
+    pub fn is_right(self) -> bool {
+        self.x() > 0
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct JoystickKeyEvent {
+    pub hat: Hat,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+    pub i: bool,
+    pub ii: bool,
+    pub a: bool,
+    pub b: bool,
+    pub c: bool,
+    pub d: bool,
+    pub l1: bool,
+    pub l2: (u8, bool),
+    pub r1: bool,
+    pub r2: (u8, bool),
+    pub rl: (u8, u8),
+    pub rr: (u8, u8),
+}
+
+/// Connection and battery of a controller
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DeviceStatus {
+    pub connected: bool,
+    /// Battery level in percent, `None` when the event is not about it
+    pub battery: Option<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum JoystickEvent {
+    Key(String, JoystickKeyEvent),
+    Home(String, bool),
+    Status(String, DeviceStatus),
+    /// Battery level dropped below the threshold, see `BatteryMonitor`
+    LowBattery(String, u8),
+}
+
+impl JoystickEvent {
+    /// Id of the device the event came from, see `Advertisement::id`
+    pub fn object_path(&self) -> &str {
+        match self {
+            JoystickEvent::Key(object_path, _)
+            | JoystickEvent::Home(object_path, _)
+            | JoystickEvent::Status(object_path, _)
+            | JoystickEvent::LowBattery(object_path, _) => object_path,
+        }
+    }
+
+    /// Key and Home events, the input of a player
+    pub fn is_input(&self) -> bool {
+        matches!(self, JoystickEvent::Key(..) | JoystickEvent::Home(..))
+    }
+}
+
+// 手柄10字节对应的按键
+// 方向键不可组合, 第8字节按顺时针取值, 0 表示未按下
+const JS_HAT: usize = 8;
+const JS_CENTERED: u8 = 0;
+const JS_UP: u8 = 1;
+const JS_UP_RIGHT: u8 = 2;
+const JS_RIGHT: u8 = 3;
+const JS_DOWN_RIGHT: u8 = 4;
+const JS_DOWN: u8 = 5;
+const JS_DOWN_LEFT: u8 = 6;
+const JS_LEFT: u8 = 7;
+const JS_UP_LEFT: u8 = 8;
+
+// 其他键可组合
+const JS_I: (usize, u8) = (7, 4);
+const JS_II: (usize, u8) = (7, 8);
+const JS_A: (usize, u8) = (6, 1);
+const JS_B: (usize, u8) = (6, 2);
+const JS_C: (usize, u8) = (6, 8);
+const JS_D: (usize, u8) = (6, 16);
+const JS_L1: (usize, u8) = (6, 0x40);
+const JS_R1: (usize, u8) = (6, 0x80);
+
+// 模拟量按键，后一个数字表示是否完全按下
+const JS_L2: (usize, usize, u8) = (4, 7, 1);
+const JS_R2: (usize, usize, u8) = (5, 7, 2);
+
+// 左右侧旋钮
+const JS_RL: (usize, usize) = (0, 1);
+const JS_RR: (usize, usize) = (2, 3);
+
+const BELL_KEY_REPORT_LEN: usize = 10;
+const BELL_HOME_REPORT_LEN: usize = 3;
+const BELL_HOME_DOWN: [u8; 3] = [8, 0, 0];
+const BELL_HOME_UP: [u8; 3] = [0, 0, 0];
+
+/// A decoded notification from the 885a characteristic
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BellReport {
+    Key(JoystickKeyEvent),
+    Home(bool),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Neither a 10 byte key report nor a 3 byte home report
+    InvalidLength(usize),
+    /// D-pad byte outside of 0..=8
+    InvalidDirection(u8),
+    /// Home report that is neither pressed nor released
+    InvalidHome([u8; 3]),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidLength(len) => write!(f, "invalid report length {}", len),
+            DecodeError::InvalidDirection(v) => write!(f, "invalid direction value {}", v),
+            DecodeError::InvalidHome(v) => write!(f, "invalid home report {:x?}", v),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl BellReport {
+    /// Decode a raw notification value, without touching D-Bus
+    pub fn parse(value: &[u8]) -> Result<BellReport, DecodeError> {
+        match value.len() {
+            BELL_KEY_REPORT_LEN => parse_key_report(value).map(BellReport::Key),
+            BELL_HOME_REPORT_LEN => {
+                let report = [value[0], value[1], value[2]];
+                match report {
+                    BELL_HOME_DOWN => Ok(BellReport::Home(true)),
+                    BELL_HOME_UP => Ok(BellReport::Home(false)),
+                    _ => Err(DecodeError::InvalidHome(report)),
+                }
+            }
+            len => Err(DecodeError::InvalidLength(len)),
+        }
+    }
+}
+
+fn parse_key_report(value: &[u8]) -> Result<JoystickKeyEvent, DecodeError> {
+    let hat = Hat::from_byte(value[JS_HAT])
+        .ok_or_else(|| DecodeError::InvalidDirection(value[JS_HAT]))?;
+
+    Ok(JoystickKeyEvent {
+        hat,
+        up: hat.is_up(),
+        down: hat.is_down(),
+        left: hat.is_left(),
+        right: hat.is_right(),
+        i: value[JS_I.0] & JS_I.1 > 0,
+        ii: value[JS_II.0] & JS_II.1 > 0,
+        a: value[JS_A.0] & JS_A.1 > 0,
+        b: value[JS_B.0] & JS_B.1 > 0,
+        c: value[JS_C.0] & JS_C.1 > 0,
+        d: value[JS_D.0] & JS_D.1 > 0,
+        l1: value[JS_L1.0] & JS_L1.1 > 0,
+        l2: (value[JS_L2.0], value[JS_L2.1] & JS_L2.2 > 0),
+        r1: value[JS_R1.0] & JS_R1.1 > 0,
+        r2: (value[JS_R2.0], value[JS_R2.1] & JS_R2.2 > 0),
+        rl: (value[JS_RL.0], value[JS_RL.1]),
+        rr: (value[JS_RR.0], value[JS_RR.1]),
+    })
+}
+
+/// The default joystick filter, every device with "bell" in its name
+/// advertising the key service
+pub fn joystick_filter() -> DeviceFilter {
+    DeviceFilter {
+        service: Some(BELL_SERVICE_UUID),
+        ..DeviceFilter::name("bell").unwrap()
+    }
+}
+
+/// Find paired joysticks known to the adapter
+pub fn get_joysticks_paired<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    filter: &DeviceFilter,
+) -> Result<Vec<Advertisement>, Error> {
+    let mut devices = vec![];
+
+    for device in backend.known_devices()? {
+        debug!(
+            target: DISCOVERY,
+            "Device: {:?} Name: {:?}, rssi: {:?}", device.id, device.name, device.rssi
+        );
+        if filter.matches(&device) {
+            devices.push(device);
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Scan for joysticks for `timeout` or until `count` of them were seen,
+/// each one once with its latest RSSI
+pub fn get_joysticks_with_event<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    filter: &DeviceFilter,
+    count: Option<usize>,
+    timeout: Duration,
+) -> Result<Vec<Advertisement>, Error> {
+    let devices = discover(backend, filter, count, timeout)?;
+    Ok(devices.iter().map(|d| d.advertisement.clone()).collect())
+}
+
+/// Scan for joysticks and add the paired ones, so that a controller found
+/// both ways is only connected once. The scan stops after `count`
+/// controllers, see `discover`, or `timeout`. At most `count` are
+/// returned.
+pub fn discover_joysticks<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    filter: &DeviceFilter,
+    count: Option<usize>,
+    timeout: Duration,
+) -> Result<Discovered, Error> {
+    let mut discovered = discover(backend, filter, count, timeout)?;
+    for device in get_joysticks_paired(backend, filter)? {
+        discovered.add(device, Instant::now());
+    }
+    if let Some(count) = count {
+        discovered.truncate(count);
+    }
+    Ok(discovered)
+}
+
+pub fn enable_joystick_notify<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    device: &str,
+) -> Result<(), Error> {
+    backend.subscribe(device, BELL_SERVICE_UUID, BELL_CHAR_UUID)
+}
+
+/// Pair if needed, connect and enable key notifications once the services
+/// are resolved, waiting up to `timeout`. Events of other devices arriving
+/// meanwhile are returned for the caller to handle. Fails at the first
+/// step that does.
+pub fn connect_joystick<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    device: &str,
+    timeout: Duration,
+) -> Result<Vec<BackendEvent>, Error> {
+    backend.pair(device)?;
+    let events = connect_and_resolve(backend, device, timeout)?;
+    enable_joystick_notify(backend, device)?;
+    Ok(events)
+}
+
+/// Decode key reports and battery levels, connection changes become
+/// status events without a battery level
+pub fn handle_ble_event(event: Option<BackendEvent>) -> Option<JoystickEvent> {
+    match event? {
+        BackendEvent::Notification {
+            device,
+            characteristic: Some(BATTERY_LEVEL_UUID),
+            value,
+        } => match parse_battery_level(&value) {
+            Some(level) => {
+                debug!(target: EVENTS, "{}: battery {}%", device, level);
+                return Some(JoystickEvent::Status(
+                    device,
+                    DeviceStatus {
+                        connected: true,
+                        battery: Some(level),
+                    },
+                ));
+            }
+            None => warn!(target: DECODE, "Invalid battery level from {}: {:x?}", device, value),
+        },
+        BackendEvent::Notification { device, value, .. } => {
+            trace!(target: DECODE, "{} {:x?}", device, value);
+            match BellReport::parse(&value) {
+                Ok(BellReport::Key(key)) => return Some(JoystickEvent::Key(device, key)),
+                Ok(BellReport::Home(down)) => return Some(JoystickEvent::Home(device, down)),
+                Err(e) => warn!(target: DECODE, "Invalid report from {}: {}", device, e),
+            }
+        }
+        BackendEvent::Connected { device, connected } => {
+            info!(
+                target: EVENTS,
+                "Device {} {}connected",
+                device,
+                if connected { "" } else { "dis" }
+            );
+            return Some(JoystickEvent::Status(
+                device,
+                DeviceStatus {
+                    connected,
+                    battery: None,
+                },
+            ));
+        }
+        _ => {}
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::{SimBackend, SimDevice};
+
+    // 抓包得到的按键数据
+    const IDLE: [u8; 10] = [0x80, 0x80, 0x80, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+    const A_AND_UP: [u8; 10] = [0x80, 0x80, 0x80, 0x80, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00];
+    const L2_FULL_R1: [u8; 10] = [0x80, 0x80, 0x80, 0x80, 0xff, 0x00, 0x80, 0x01, 0x00, 0x00];
+    const STICKS_I_II: [u8; 10] = [0x00, 0xff, 0x12, 0x34, 0x00, 0x40, 0x00, 0x0c, 0x00, 0x00];
+
+    #[test]
+    fn parse_idle_report() {
+        let report = BellReport::parse(&IDLE).unwrap();
+        assert_eq!(
+            report,
+            BellReport::Key(JoystickKeyEvent {
+                rl: (0x80, 0x80),
+                rr: (0x80, 0x80),
+                ..Default::default()
+            })
+        );
+    }
+
+    #[test]
+    fn parse_buttons_and_direction() {
+        match BellReport::parse(&A_AND_UP).unwrap() {
+            BellReport::Key(key) => {
+                assert_eq!(key.hat, Hat::Up);
+                assert!(key.a && key.up);
+                assert!(!key.b && !key.down && !key.left && !key.right);
+            }
+            r => panic!("unexpected report {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_diagonal_directions() {
+        let expected = [
+            (2, Hat::UpRight, (true, false, false, true)),
+            (4, Hat::DownRight, (false, true, false, true)),
+            (6, Hat::DownLeft, (false, true, true, false)),
+            (8, Hat::UpLeft, (true, false, true, false)),
+        ];
+        for (byte, hat, (up, down, left, right)) in expected.iter() {
+            let mut value = IDLE;
+            value[8] = *byte;
+            match BellReport::parse(&value).unwrap() {
+                BellReport::Key(key) => {
+                    assert_eq!(key.hat, *hat);
+                    assert_eq!(
+                        (key.up, key.down, key.left, key.right),
+                        (*up, *down, *left, *right)
+                    );
+                }
+                r => panic!("unexpected report {:?}", r),
+            }
+        }
+    }
+
+    #[test]
+    fn parse_analog_triggers() {
+        match BellReport::parse(&L2_FULL_R1).unwrap() {
+            BellReport::Key(key) => {
+                assert_eq!(key.l2, (0xff, true));
+                assert_eq!(key.r2, (0x00, false));
+                assert!(key.r1 && !key.l1);
+            }
+            r => panic!("unexpected report {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_sticks_and_select_keys() {
+        match BellReport::parse(&STICKS_I_II).unwrap() {
+            BellReport::Key(key) => {
+                assert_eq!(key.rl, (0x00, 0xff));
+                assert_eq!(key.rr, (0x12, 0x34));
+                assert_eq!(key.r2, (0x40, false));
+                assert!(key.i && key.ii);
+            }
+            r => panic!("unexpected report {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_home_report() {
+        assert_eq!(BellReport::parse(&[8, 0, 0]), Ok(BellReport::Home(true)));
+        assert_eq!(BellReport::parse(&[0, 0, 0]), Ok(BellReport::Home(false)));
+        assert_eq!(
+            BellReport::parse(&[1, 2, 3]),
+            Err(DecodeError::InvalidHome([1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn reject_invalid_reports() {
+        assert_eq!(BellReport::parse(&[]), Err(DecodeError::InvalidLength(0)));
+        assert_eq!(
+            BellReport::parse(&[0; 6]),
+            Err(DecodeError::InvalidLength(6))
+        );
+
+        let mut value = IDLE;
+        value[8] = 9;
+        assert_eq!(
+            BellReport::parse(&value),
+            Err(DecodeError::InvalidDirection(9))
+        );
+    }
+
+    const BELL: &str = "E0:7D:EA:00:00:01";
+
+    fn bell_backend() -> SimBackend {
+        let mut sim = SimBackend::new();
+        sim.add_device(
+            SimDevice::new(BELL, "bell-controller")
+                .rssi(-48)
+                .service(BELL_SERVICE_UUID, &[BELL_CHAR_UUID]),
+        )
+        .add_device(SimDevice::new("00:81:F9:DF:B0:40", "MMC").rssi(-70));
+        sim
+    }
+
+    fn events(sim: &mut SimBackend) -> Vec<JoystickEvent> {
+        let mut events = vec![];
+        while let Some(event) = sim.next_event(Duration::from_secs(1)).unwrap() {
+            events.extend(handle_ble_event(Some(event)));
+        }
+        events
+    }
+
+    #[test]
+    fn discover_and_connect_joystick() {
+        let mut sim = bell_backend();
+        sim.notify(BELL, BELL_CHAR_UUID, &A_AND_UP)
+            .notify(BELL, BELL_CHAR_UUID, &BELL_HOME_DOWN);
+
+        let filter = joystick_filter();
+        assert!(get_joysticks_paired(&mut sim, &filter).unwrap().is_empty());
+        let joysticks =
+            get_joysticks_with_event(&mut sim, &filter, None, Duration::from_secs(1)).unwrap();
+        assert_eq!(joysticks.len(), 1);
+        assert_eq!(joysticks[0].rssi, Some(-48));
+
+        connect_joystick(&mut sim, &joysticks[0].id, Duration::from_secs(1)).unwrap();
+        assert!(sim.is_subscribed(BELL, BELL_CHAR_UUID));
+        let paired = get_joysticks_paired(&mut sim, &filter).unwrap();
+        assert_eq!(paired.len(), 1);
+        assert!(paired[0].paired);
+
+        // 扫描到的和已配对的是同一个手柄, 只连接一次
+        let discovered =
+            discover_joysticks(&mut sim, &filter, None, Duration::from_secs(1)).unwrap();
+        assert_eq!(discovered.len(), 1);
+        let joystick = &discovered.get(BELL).unwrap().advertisement;
+        assert_eq!(joystick.rssi, Some(-48));
+        assert!(joystick.paired);
+
+        let events = events(&mut sim);
+        assert_eq!(events.len(), 2);
+        match &events[0] {
+            JoystickEvent::Key(device, key) => {
+                assert_eq!(device, BELL);
+                assert!(key.a && key.up);
+            }
+            e => panic!("unexpected event {:?}", e),
+        }
+        assert_eq!(events[1], JoystickEvent::Home(BELL.to_string(), true));
+    }
+
+    #[test]
+    fn discover_at_most_count_joysticks() {
+        const OTHER: &str = "E0:7D:EA:00:00:02";
+        let mut sim = bell_backend();
+        sim.add_device(
+            SimDevice::new(OTHER, "bell-controller").service(BELL_SERVICE_UUID, &[BELL_CHAR_UUID]),
+        );
+        let filter = joystick_filter();
+        connect_joystick(&mut sim, OTHER, Duration::from_secs(1)).unwrap();
+        connect_joystick(&mut sim, BELL, Duration::from_secs(1)).unwrap();
+
+        let joysticks =
+            get_joysticks_with_event(&mut sim, &filter, Some(1), Duration::from_secs(1)).unwrap();
+        assert_eq!(joysticks.len(), 1);
+        // 扫描到一个就停了, 已配对的那个不能让总数超过 count
+        let discovered =
+            discover_joysticks(&mut sim, &filter, Some(1), Duration::from_secs(1)).unwrap();
+        assert_eq!(discovered.len(), 1);
+        assert_eq!(
+            discover_joysticks(&mut sim, &filter, None, Duration::from_secs(1))
+                .unwrap()
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn connect_failure_leaves_joystick_silent() {
+        let mut sim = SimBackend::new();
+        sim.add_device(
+            SimDevice::new(BELL, "bell-controller")
+                .service(BELL_SERVICE_UUID, &[BELL_CHAR_UUID])
+                .connect_error("Page timeout"),
+        )
+        .notify(BELL, BELL_CHAR_UUID, &A_AND_UP);
+
+        match connect_joystick(&mut sim, BELL, Duration::from_secs(1)) {
+            Err(Error::Backend(e)) => assert_eq!(e, "Page timeout"),
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(!sim.is_connected(BELL));
+        assert!(events(&mut sim).is_empty());
+    }
+}
@@ -0,0 +1,30 @@
+//! Bluetooth LE support for the Bell joystick and the MMC thermometer.
+//!
+//! Run this command to turn on bluetooth first:
+//! bluetoothctl power on
+
+pub mod backend;
+pub mod battery;
+pub mod ble_uuid;
+pub mod capture;
+pub mod cli;
+pub mod controller;
+pub mod discovery;
+pub mod error;
+#[cfg(feature = "blurz")]
+pub mod gatt;
+pub mod gatt_dump;
+pub mod input;
+pub mod joystick;
+pub mod logging;
+#[cfg(unix)]
+pub mod notify;
+#[cfg(feature = "blurz")]
+pub mod pairing;
+pub mod sig;
+#[cfg(feature = "async-std")]
+pub mod stream;
+pub mod supervisor;
+pub mod thermometer;
+#[cfg(target_os = "linux")]
+pub mod uinput;
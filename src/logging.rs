@@ -0,0 +1,52 @@
+//! Log targets and the logger of the binaries.
+//!
+//! The library logs through the `log` crate under a few fixed targets, so a
+//! deployment can turn up one area without the others, e.g.
+//! `RUST_LOG=decode=trace`. Raw notification bytes are only logged at trace
+//! level.
+
+use log::LevelFilter;
+
+/// Scanning and picking devices
+pub const DISCOVERY: &str = "discovery";
+/// Services, characteristics and descriptors
+pub const GATT: &str = "gatt";
+/// Decoding notifications, raw packets at trace level
+pub const DECODE: &str = "decode";
+/// Connection state and backend events
+pub const EVENTS: &str = "events";
+
+/// Level for `verbosity` as counted by the `-v` and `-q` options, info at 0
+pub fn level(verbosity: i8) -> LevelFilter {
+    match verbosity {
+        i8::MIN..=-3 => LevelFilter::Off,
+        -2 => LevelFilter::Error,
+        -1 => LevelFilter::Warn,
+        0 => LevelFilter::Info,
+        1 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Log to stderr at the level of `verbosity`. RUST_LOG is applied on top,
+/// to set single targets.
+pub fn init(verbosity: i8) {
+    env_logger::Builder::new()
+        .filter_level(level(verbosity))
+        .parse_env("RUST_LOG")
+        .init();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verbosity_levels() {
+        assert_eq!(level(0), LevelFilter::Info);
+        assert_eq!(level(-1), LevelFilter::Warn);
+        assert_eq!(level(2), LevelFilter::Trace);
+        assert_eq!(level(5), LevelFilter::Trace);
+        assert_eq!(level(-10), LevelFilter::Off);
+    }
+}
@@ -0,0 +1,217 @@
+//! Notifications read straight from the socket BlueZ hands out on
+//! AcquireNotify, bypassing the D-Bus PropertiesChanged signals.
+//!
+//! The socket is SOCK_SEQPACKET, every read returns one whole notification
+//! of at most MTU bytes.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::time::{Duration, Instant};
+
+pub struct NotifySocket {
+    file: File,
+    mtu: usize,
+}
+
+impl NotifySocket {
+    /// Take over `fd` as returned by AcquireNotify.
+    ///
+    /// # Safety
+    ///
+    /// `fd` has to be an open socket that nothing else closes.
+    pub unsafe fn from_raw_fd(fd: RawFd, mtu: u16) -> NotifySocket {
+        NotifySocket {
+            file: File::from_raw_fd(fd),
+            mtu: mtu as usize,
+        }
+    }
+
+    pub fn mtu(&self) -> usize {
+        self.mtu
+    }
+
+    /// Read one notification, blocking until there is one. BlueZ closes the
+    /// socket when the device disconnects, that is reported as
+    /// `UnexpectedEof`.
+    pub fn read_packet(&mut self) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; self.mtu];
+        let n = self.file.read(&mut buf)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "notify socket closed",
+            ));
+        }
+        buf.truncate(n);
+        Ok(buf)
+    }
+}
+
+impl AsRawFd for NotifySocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Wait up to `timeout` for any of `fds` to become readable, returns which
+/// ones are. A hung up or failed fd counts as readable, reading it reports
+/// the error.
+pub fn poll_readable(fds: &[RawFd], timeout: Duration) -> io::Result<Vec<bool>> {
+    let mut pollfds = fds
+        .iter()
+        .map(|fd| libc::pollfd {
+            fd: *fd,
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect::<Vec<_>>();
+    let timeout_ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    let r = unsafe {
+        libc::poll(
+            pollfds.as_mut_ptr(),
+            pollfds.len() as libc::nfds_t,
+            timeout_ms,
+        )
+    };
+    if r < 0 {
+        let e = io::Error::last_os_error();
+        // 被信号打断当作超时
+        if e.kind() == io::ErrorKind::Interrupted {
+            return Ok(vec![false; fds.len()]);
+        }
+        return Err(e);
+    }
+    Ok(pollfds
+        .iter()
+        .map(|p| p.revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0)
+        .collect())
+}
+
+/// Counters of one notification path, to compare the AcquireNotify socket
+/// with the D-Bus signals
+#[derive(Clone, Debug, Default)]
+pub struct NotifyStats {
+    pub packets: u64,
+    pub bytes: u64,
+    first: Option<Instant>,
+    last: Option<Instant>,
+    /// From the fd being readable to the notification being handed out
+    total_latency: Duration,
+    pub max_latency: Duration,
+}
+
+impl NotifyStats {
+    /// Count a notification of `len` bytes whose fd became readable at
+    /// `readable_at`
+    pub fn record(&mut self, len: usize, readable_at: Instant) {
+        let now = Instant::now();
+        let latency = now.saturating_duration_since(readable_at);
+        self.packets += 1;
+        self.bytes += len as u64;
+        self.first.get_or_insert(now);
+        self.last = Some(now);
+        self.total_latency += latency;
+        self.max_latency = self.max_latency.max(latency);
+    }
+
+    pub fn mean_latency(&self) -> Duration {
+        if self.packets == 0 {
+            Duration::from_secs(0)
+        } else {
+            self.total_latency / self.packets as u32
+        }
+    }
+
+    /// Notifications per second between the first and the last one
+    pub fn rate(&self) -> f64 {
+        match (self.first, self.last) {
+            (Some(first), Some(last)) if last > first => {
+                (self.packets - 1) as f64 / (last - first).as_secs_f64()
+            }
+            _ => 0.0,
+        }
+    }
+
+    /// Bytes per second between the first and the last notification
+    pub fn throughput(&self) -> f64 {
+        if self.packets == 0 {
+            0.0
+        } else {
+            self.rate() * self.bytes as f64 / self.packets as f64
+        }
+    }
+}
+
+impl fmt::Display for NotifyStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} packets, {:.1}/s, {:.0} B/s, latency mean {:?} max {:?}",
+            self.packets,
+            self.rate(),
+            self.throughput(),
+            self.mean_latency(),
+            self.max_latency
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn seqpacket_pair() -> (File, NotifySocket) {
+        let mut fds = [0; 2];
+        let r =
+            unsafe { libc::socketpair(libc::AF_UNIX, libc::SOCK_SEQPACKET, 0, fds.as_mut_ptr()) };
+        assert_eq!(r, 0);
+        unsafe {
+            (
+                File::from_raw_fd(fds[0]),
+                NotifySocket::from_raw_fd(fds[1], 23),
+            )
+        }
+    }
+
+    #[test]
+    fn one_notification_per_read() {
+        let (mut bluez, mut socket) = seqpacket_pair();
+        let fd = socket.as_raw_fd();
+        assert_eq!(
+            poll_readable(&[fd], Duration::from_millis(0)).unwrap(),
+            vec![false]
+        );
+
+        bluez.write_all(&[8, 0, 0]).unwrap();
+        bluez
+            .write_all(&[0x80, 0x80, 0x80, 0x80, 0, 0, 0, 0, 0, 0])
+            .unwrap();
+        assert_eq!(
+            poll_readable(&[fd], Duration::from_millis(0)).unwrap(),
+            vec![true]
+        );
+        assert_eq!(socket.read_packet().unwrap(), vec![8, 0, 0]);
+        assert_eq!(socket.read_packet().unwrap().len(), 10);
+
+        drop(bluez);
+        let e = socket.read_packet().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn stats_count_packets() {
+        let mut stats = NotifyStats::default();
+        assert_eq!(stats.rate(), 0.0);
+
+        let readable_at = Instant::now();
+        stats.record(10, readable_at);
+        stats.record(3, readable_at);
+        assert_eq!(stats.packets, 2);
+        assert_eq!(stats.bytes, 13);
+        assert!(stats.max_latency >= stats.mean_latency());
+        assert!(stats.to_string().starts_with("2 packets"));
+    }
+}
@@ -0,0 +1,263 @@
+//! Pairing and bonding over BlueZ, with our own `org.bluez.Agent1`.
+//!
+//! The controllers have no display or keys, so the agent registers as
+//! NoInputNoOutput and BlueZ falls back to Just Works pairing. Without a
+//! registered agent some BlueZ versions refuse to pair at all.
+
+use crate::error::{dbus, Error};
+use crate::logging::DISCOVERY;
+use blurz::bluetooth_adapter::BluetoothAdapter;
+use blurz::bluetooth_device::BluetoothDevice;
+use blurz::bluetooth_session::BluetoothSession;
+use dbus::{Message, MessageType, Path, Props};
+use log::{debug, info, warn};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Object path of our agent on the session's connection
+pub const AGENT_PATH: &str = "/org/bell_ble_controller/agent";
+
+const AGENT_CAPABILITY: &str = "NoInputNoOutput";
+
+/// How long `pair_device` waits by default, BlueZ gives up after 30s too
+pub const PAIR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The answer of a NoInputNoOutput agent to the Agent1 method `member`,
+/// the D-Bus error name when it rejects it
+fn answer(member: &str) -> Result<(), &'static str> {
+    match member {
+        // Just Works 配对和服务授权直接同意
+        "Release"
+        | "Cancel"
+        | "RequestAuthorization"
+        | "RequestConfirmation"
+        | "AuthorizeService"
+        | "DisplayPasskey"
+        | "DisplayPinCode" => Ok(()),
+        // 没有键盘, 输不了 PIN 或者 passkey
+        "RequestPinCode" | "RequestPasskey" => Err("org.bluez.Error.Rejected"),
+        _ => Err("org.freedesktop.DBus.Error.UnknownMethod"),
+    }
+}
+
+/// Registered while alive, unregistered on drop
+pub struct Agent<'a> {
+    session: &'a BluetoothSession,
+}
+
+impl<'a> Agent<'a> {
+    /// Export the agent at `AGENT_PATH` and make it the default agent
+    pub fn register(session: &'a BluetoothSession) -> Result<Agent<'a>, Error> {
+        let connection = session.get_connection();
+        connection
+            .register_object_path(AGENT_PATH)
+            .map_err(|e| Error::Dbus(e.to_string()))?;
+        // 先构造出来, 注册失败时 drop 会把 object path 清掉
+        let agent = Agent { session };
+        agent.call_agent_manager("RegisterAgent", Some(AGENT_CAPABILITY))?;
+        agent.call_agent_manager("RequestDefaultAgent", None)?;
+        debug!(target: DISCOVERY, "Agent registered at {}", AGENT_PATH);
+        Ok(agent)
+    }
+
+    fn call_agent_manager(&self, method: &str, capability: Option<&str>) -> Result<(), Error> {
+        let message =
+            Message::new_method_call("org.bluez", "/org/bluez", "org.bluez.AgentManager1", method)
+                .map_err(Error::Dbus)?
+                .append1(Path::from(AGENT_PATH));
+        let message = match capability {
+            Some(capability) => message.append1(capability),
+            None => message,
+        };
+        self.session
+            .get_connection()
+            .send_with_reply_and_block(message, 1000)
+            .map_err(|e| Error::Dbus(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Answer `message` if it is a call to the agent, returns whether it was
+    fn handle(&self, message: &Message) -> bool {
+        if message.msg_type() != MessageType::MethodCall
+            || message.path().as_deref() != Some(AGENT_PATH)
+        {
+            return false;
+        }
+        let member = message.member().map(|m| m.to_string()).unwrap_or_default();
+        debug!(target: DISCOVERY, "Agent: {}", member);
+        let reply = match answer(&member) {
+            Ok(()) => Message::new_method_return(message),
+            Err(name) => Message::new_error(message, name, &member),
+        };
+        if let Some(reply) = reply {
+            if self.session.get_connection().send(reply).is_err() {
+                warn!(target: DISCOVERY, "Failed to answer agent call {}", member);
+            }
+        }
+        true
+    }
+}
+
+impl<'a> Drop for Agent<'a> {
+    fn drop(&mut self) {
+        self.call_agent_manager("UnregisterAgent", None).ok();
+        self.session
+            .get_connection()
+            .unregister_object_path(AGENT_PATH);
+    }
+}
+
+/// Pair with the device at object path `device`, answering the agent
+/// calls meanwhile. A device that is already paired is fine. Every other
+/// message received while waiting is passed to `other`, so that signals
+/// of the devices already in use are not lost.
+pub fn pair_device(
+    session: &BluetoothSession,
+    agent: &Agent,
+    device: &str,
+    timeout: Duration,
+    other: &mut dyn FnMut(Message),
+) -> Result<(), Error> {
+    let connection = session.get_connection();
+    let message = Message::new_method_call("org.bluez", device, "org.bluez.Device1", "Pair")
+        .map_err(Error::Dbus)?;
+    // Pair 要等 agent 回话, 不能用 send_with_reply_and_block 把自己堵住
+    let serial = connection
+        .send(message)
+        .map_err(|_| Error::Dbus(format!("Failed to send Pair to {}", device)))?;
+
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        for mut message in connection.incoming(100) {
+            if agent.handle(&message) {
+                continue;
+            }
+            if message.get_reply_serial() != Some(serial) {
+                other(message);
+                continue;
+            }
+            return match message.as_result() {
+                Ok(_) => Ok(()),
+                Err(e) if e.name() == Some("org.bluez.Error.AlreadyExists") => Ok(()),
+                Err(e) => Err(Error::Dbus(format!(
+                    "Pairing {} failed: {}",
+                    device,
+                    e.message().unwrap_or("unknown error")
+                ))),
+            };
+        }
+    }
+    Err(Error::Timeout {
+        device: device.to_string(),
+        operation: "pairing",
+        timeout,
+    })
+}
+
+/// Pairing state of a device as BlueZ reports it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BondState {
+    pub paired: bool,
+    /// Keys are stored, so the device can reconnect without pairing again
+    pub bonded: bool,
+    /// BlueZ accepts connections from it without asking the agent
+    pub trusted: bool,
+    pub connected: bool,
+}
+
+impl fmt::Display for BondState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "paired: {}, bonded: {}, trusted: {}, connected: {}",
+            self.paired, self.bonded, self.trusted, self.connected
+        )
+    }
+}
+
+pub fn bond_state(session: &BluetoothSession, device: &str) -> Result<BondState, Error> {
+    let d = BluetoothDevice::new(session, device.to_string());
+    let paired = d.is_paired().map_err(dbus)?;
+    // Bonded 属性 BlueZ 5.67 才有, 老版本配对了就会保存密钥
+    let bonded = Props::new(
+        session.get_connection(),
+        "org.bluez",
+        device,
+        "org.bluez.Device1",
+        1000,
+    )
+    .get("Bonded")
+    .ok()
+    .and_then(|bonded| bonded.inner::<bool>().ok())
+    .unwrap_or(paired);
+    Ok(BondState {
+        paired,
+        bonded,
+        trusted: d.is_trusted().map_err(dbus)?,
+        connected: d.is_connected().map_err(dbus)?,
+    })
+}
+
+/// Pair with a registered agent and mark the device trusted, so BlueZ
+/// reconnects it by itself. Unrelated messages go to `other`, see
+/// `pair_device`.
+pub fn pair_and_trust(
+    session: &BluetoothSession,
+    device: &str,
+    timeout: Duration,
+    other: &mut dyn FnMut(Message),
+) -> Result<BondState, Error> {
+    let d = BluetoothDevice::new(session, device.to_string());
+    if !d.is_paired().map_err(dbus)? {
+        let agent = Agent::register(session)?;
+        pair_device(session, &agent, device, timeout, other)?;
+        info!(target: DISCOVERY, "Paired with {}", device);
+    }
+    if !d.is_trusted().map_err(dbus)? {
+        d.set_trusted(true).map_err(dbus)?;
+    }
+    bond_state(session, device)
+}
+
+/// Remove the device and its keys from the adapter at object path
+/// `adapter`. It has to be paired again before it can be used.
+pub fn forget_device(session: &BluetoothSession, adapter: &str, device: &str) -> Result<(), Error> {
+    BluetoothAdapter::create_adapter(session, adapter.to_string())
+        .map_err(dbus)?
+        .remove_device(device.to_string())
+        .map_err(dbus)
+}
+
+/// Object paths and states of the paired devices of the adapter at
+/// object path `adapter`
+pub fn bonded_devices(
+    session: &BluetoothSession,
+    adapter: &str,
+) -> Result<Vec<(String, BondState)>, Error> {
+    let mut devices = vec![];
+    let adapter = BluetoothAdapter::create_adapter(session, adapter.to_string()).map_err(dbus)?;
+    for device in adapter.get_device_list().map_err(dbus)? {
+        let state = bond_state(session, &device)?;
+        if state.paired || state.bonded {
+            devices.push((device, state));
+        }
+    }
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agent_answers() {
+        assert_eq!(answer("RequestAuthorization"), Ok(()));
+        assert_eq!(answer("RequestConfirmation"), Ok(()));
+        assert_eq!(answer("AuthorizeService"), Ok(()));
+        assert_eq!(answer("RequestPasskey"), Err("org.bluez.Error.Rejected"));
+        assert_eq!(
+            answer("Introspect"),
+            Err("org.freedesktop.DBus.Error.UnknownMethod")
+        );
+    }
+}
@@ -0,0 +1,211 @@
+//! Names of the Bluetooth SIG assigned numbers and decoding of the standard
+//! descriptors, for the GATT explorer.
+//!
+//! Only the 16 bit assigned numbers have names, vendor UUIDs get `None`.
+
+use crate::ble_uuid::BleUuid;
+
+/// (assigned number, name) of the services
+const SERVICES: &[(u16, &str)] = &[
+    (0x1800, "Generic Access"),
+    (0x1801, "Generic Attribute"),
+    (0x1802, "Immediate Alert"),
+    (0x1803, "Link Loss"),
+    (0x1804, "Tx Power"),
+    (0x1805, "Current Time"),
+    (0x1808, "Glucose"),
+    (0x1809, "Health Thermometer"),
+    (0x180a, "Device Information"),
+    (0x180d, "Heart Rate"),
+    (0x180f, "Battery"),
+    (0x1810, "Blood Pressure"),
+    (0x1812, "Human Interface Device"),
+    (0x1813, "Scan Parameters"),
+    (0x1816, "Cycling Speed and Cadence"),
+    (0x1819, "Location and Navigation"),
+    (0x181a, "Environmental Sensing"),
+    (0x181c, "User Data"),
+    (0x181d, "Weight Scale"),
+];
+
+const CHARACTERISTICS: &[(u16, &str)] = &[
+    (0x2a00, "Device Name"),
+    (0x2a01, "Appearance"),
+    (0x2a04, "Peripheral Preferred Connection Parameters"),
+    (0x2a05, "Service Changed"),
+    (0x2a19, "Battery Level"),
+    (0x2a1c, "Temperature Measurement"),
+    (0x2a1d, "Temperature Type"),
+    (0x2a1e, "Intermediate Temperature"),
+    (0x2a21, "Measurement Interval"),
+    (0x2a22, "Boot Keyboard Input Report"),
+    (0x2a23, "System ID"),
+    (0x2a24, "Model Number String"),
+    (0x2a25, "Serial Number String"),
+    (0x2a26, "Firmware Revision String"),
+    (0x2a27, "Hardware Revision String"),
+    (0x2a28, "Software Revision String"),
+    (0x2a29, "Manufacturer Name String"),
+    (
+        0x2a2a,
+        "IEEE 11073-20601 Regulatory Certification Data List",
+    ),
+    (0x2a33, "Boot Mouse Input Report"),
+    (0x2a37, "Heart Rate Measurement"),
+    (0x2a4a, "HID Information"),
+    (0x2a4b, "Report Map"),
+    (0x2a4c, "HID Control Point"),
+    (0x2a4d, "Report"),
+    (0x2a4e, "Protocol Mode"),
+    (0x2a50, "PnP ID"),
+    (0x2a6e, "Temperature"),
+    (0x2a6f, "Humidity"),
+    (0x2aa6, "Central Address Resolution"),
+];
+
+const DESCRIPTORS: &[(u16, &str)] = &[
+    (0x2900, "Characteristic Extended Properties"),
+    (0x2901, "Characteristic User Description"),
+    (0x2902, "Client Characteristic Configuration"),
+    (0x2903, "Server Characteristic Configuration"),
+    (0x2904, "Characteristic Presentation Format"),
+    (0x2905, "Characteristic Aggregate Format"),
+    (0x2906, "Valid Range"),
+    (0x2907, "External Report Reference"),
+    (0x2908, "Report Reference"),
+];
+
+/// Format types of the Presentation Format descriptor, indexed by value
+const FORMATS: &[&str] = &[
+    "rfu", "boolean", "2bit", "nibble", "uint8", "uint12", "uint16", "uint24", "uint32", "uint48",
+    "uint64", "uint128", "sint8", "sint12", "sint16", "sint24", "sint32", "sint48", "sint64",
+    "sint128", "float32", "float64", "SFLOAT", "FLOAT", "duint16", "utf8s", "utf16s", "struct",
+];
+
+/// Units of the Presentation Format descriptor
+const UNITS: &[(u16, &str)] = &[
+    (0x2700, "unitless"),
+    (0x2701, "metre"),
+    (0x2702, "kilogram"),
+    (0x2703, "second"),
+    (0x2704, "ampere"),
+    (0x2705, "kelvin"),
+    (0x2724, "pascal"),
+    (0x2728, "volt"),
+    (0x272f, "degree Celsius"),
+    (0x27a7, "millisecond"),
+    (0x27ac, "degree Fahrenheit"),
+    (0x27ad, "percentage"),
+    (0x27af, "beats per minute"),
+];
+
+fn lookup(table: &'static [(u16, &'static str)], uuid: BleUuid) -> Option<&'static str> {
+    let number = uuid.to_u16()?;
+    table
+        .iter()
+        .find(|(n, _)| *n == number)
+        .map(|(_, name)| *name)
+}
+
+pub fn service_name(uuid: BleUuid) -> Option<&'static str> {
+    lookup(SERVICES, uuid)
+}
+
+pub fn characteristic_name(uuid: BleUuid) -> Option<&'static str> {
+    lookup(CHARACTERISTICS, uuid)
+}
+
+pub fn descriptor_name(uuid: BleUuid) -> Option<&'static str> {
+    lookup(DESCRIPTORS, uuid)
+}
+
+/// Names of the bits set in the little endian `value`, hex if there is any
+/// bit without a name
+fn bits(value: &[u8], names: &[&str]) -> String {
+    let mut bits = 0u32;
+    for (i, b) in value.iter().take(4).enumerate() {
+        bits |= (*b as u32) << (8 * i);
+    }
+    let set = names
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| bits & (1 << i) != 0)
+        .map(|(_, name)| *name)
+        .collect::<Vec<_>>();
+    if bits >> names.len() != 0 {
+        format!("{:#06x}", bits)
+    } else if set.is_empty() {
+        "none".to_string()
+    } else {
+        set.join(", ")
+    }
+}
+
+/// Readable form of the value of the descriptor `uuid`, the bytes in hex
+/// for the ones not known
+pub fn decode_descriptor(uuid: BleUuid, value: &[u8]) -> String {
+    match (uuid.to_u16(), value) {
+        (Some(0x2900), _) => bits(value, &["reliable write", "writable auxiliaries"]),
+        (Some(0x2901), _) => String::from_utf8_lossy(value).to_string(),
+        (Some(0x2902), _) => bits(value, &["notifications", "indications"]),
+        (Some(0x2903), _) => bits(value, &["broadcasts"]),
+        (Some(0x2904), [format, exponent, unit_lo, unit_hi, _, _, _]) => {
+            let unit = u16::from_le_bytes([*unit_lo, *unit_hi]);
+            format!(
+                "format {}, exponent {}, unit {} ({:#06x})",
+                FORMATS.get(*format as usize).unwrap_or(&"reserved"),
+                *exponent as i8,
+                UNITS
+                    .iter()
+                    .find(|(u, _)| *u == unit)
+                    .map_or("unknown", |(_, name)| *name),
+                unit
+            )
+        }
+        _ => format!("{:02x?}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uuid(short: u16) -> BleUuid {
+        BleUuid::from_u16(short)
+    }
+
+    #[test]
+    fn names_of_assigned_numbers() {
+        assert_eq!(service_name(uuid(0x180f)), Some("Battery"));
+        assert_eq!(service_name(uuid(0x180a)), Some("Device Information"));
+        assert_eq!(characteristic_name(uuid(0x2a19)), Some("Battery Level"));
+        assert_eq!(
+            descriptor_name(uuid(0x2902)),
+            Some("Client Characteristic Configuration")
+        );
+        assert_eq!(service_name(uuid(0x8850)), None);
+        assert_eq!(service_name(BleUuid::from_u32(0x0001_180f)), None);
+    }
+
+    #[test]
+    fn decode_known_descriptors() {
+        assert_eq!(decode_descriptor(uuid(0x2901), b"Battery"), "Battery");
+        assert_eq!(decode_descriptor(uuid(0x2902), &[0, 0]), "none");
+        assert_eq!(decode_descriptor(uuid(0x2902), &[1, 0]), "notifications");
+        assert_eq!(
+            decode_descriptor(uuid(0x2902), &[3, 0]),
+            "notifications, indications"
+        );
+        assert_eq!(decode_descriptor(uuid(0x2902), &[4, 0]), "0x0004");
+        assert_eq!(
+            decode_descriptor(uuid(0x2904), &[0x04, 0x00, 0xad, 0x27, 0x01, 0x00, 0x00]),
+            "format uint8, exponent 0, unit percentage (0x27ad)"
+        );
+        assert_eq!(
+            decode_descriptor(uuid(0x2904), &[0x0e, 0xfe, 0x2f, 0x27, 0x01, 0x00, 0x00]),
+            "format sint16, exponent -2, unit degree Celsius (0x272f)"
+        );
+        assert_eq!(decode_descriptor(uuid(0x2904), &[0x04]), "[04]");
+        assert_eq!(decode_descriptor(uuid(0x2908), &[1, 2]), "[01, 02]");
+    }
+}
@@ -0,0 +1,294 @@
+//! Controller and thermometer events as async streams.
+//!
+//! The backends block and are not `Send`, so a single thread owns the
+//! backend, keeps every device connected and feeds a channel. The streams
+//! read the other end and can be `select!`ed with other futures.
+//!
+//! ```no_run
+//! use async_std::stream::StreamExt;
+//! use async_std::task;
+//! use bell_ble_controller::cli::Options;
+//! use bell_ble_controller::joystick::joystick_filter;
+//! use bell_ble_controller::stream::joystick_events;
+//!
+//! task::block_on(async {
+//!     let options = Options::from_env(joystick_filter());
+//!     let mut events = joystick_events(options).await.unwrap();
+//!     while let Some(event) = events.next().await {
+//!         println!("{:?}", event);
+//!     }
+//! });
+//! ```
+
+use crate::backend::{default_backend, BackendEvent, BleBackend, RESOLVE_TIMEOUT};
+use crate::battery::{
+    BatteryMonitor, BATTERY_LEVEL_UUID, BATTERY_SERVICE_UUID, LOW_BATTERY_THRESHOLD,
+};
+use crate::ble_uuid::BleUuid;
+use crate::cli::Options;
+use crate::error::Error;
+use crate::joystick::{
+    discover_joysticks, handle_ble_event, JoystickEvent, BELL_CHAR_UUID, BELL_SERVICE_UUID,
+};
+use crate::logging::EVENTS;
+use crate::supervisor::{Backoff, Retry, Supervisor};
+use crate::thermometer::{
+    find_thermometer, handle_thermometer_event, TemperatureMeasurement, MMC_CHAR_UUID,
+    MMC_SERVICE_UUID,
+};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::stream::{Stream, StreamExt};
+use log::{error, warn};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Run `setup` on a thread of its own, then pump the events of the backend
+/// it returns into the channel, while the supervisor keeps the devices it
+/// was given connected. The thread stops once the receiver is dropped.
+pub async fn backend_events<S>(setup: S) -> Result<Receiver<BackendEvent>, Error>
+where
+    S: FnOnce() -> Result<(Box<dyn BleBackend>, Supervisor), Error> + Send + 'static,
+{
+    let (ready_sender, ready) = channel::bounded(1);
+    let (sender, events) = channel::unbounded();
+
+    thread::spawn(move || match setup() {
+        Ok((mut backend, mut supervisor)) => {
+            ready_sender.try_send(Ok(())).ok();
+            pump(backend.as_mut(), &mut supervisor, &sender);
+        }
+        Err(e) => {
+            ready_sender.try_send(Err(e)).ok();
+        }
+    });
+
+    match ready.recv().await {
+        Ok(Ok(())) => Ok(events),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(Error::Backend("Backend thread exited".to_string())),
+    }
+}
+
+fn pump(backend: &mut dyn BleBackend, supervisor: &mut Supervisor, sender: &Sender<BackendEvent>) {
+    let mut retry = Retry::new(Backoff::default());
+    while !sender.is_closed() {
+        supervisor.poll(backend, Instant::now());
+        let mut events = supervisor.take_events();
+        match backend.next_event(POLL_INTERVAL) {
+            Ok(Some(event)) => {
+                retry.succeeded();
+                events.push(event);
+            }
+            Ok(None) => retry.succeeded(),
+            Err(e) => {
+                warn!(target: EVENTS, "Failed to receive event: {}", e);
+                // 出错就结束线程, 通道关闭之后事件流也就结束了
+                if let Err(e) = retry.failed(e) {
+                    error!(target: EVENTS, "Giving up receiving events: {}", e);
+                    return;
+                }
+            }
+        }
+        for event in events {
+            supervisor.handle_event(&event, Instant::now());
+            if sender.try_send(event).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Decoded controller events, each status event with a battery level
+/// below the threshold followed by a `LowBattery` one
+struct JoystickEvents {
+    events: Receiver<BackendEvent>,
+    battery: BatteryMonitor,
+    pending: Option<JoystickEvent>,
+}
+
+impl JoystickEvents {
+    fn new(events: Receiver<BackendEvent>, battery: BatteryMonitor) -> JoystickEvents {
+        JoystickEvents {
+            events,
+            battery,
+            pending: None,
+        }
+    }
+}
+
+impl Stream for JoystickEvents {
+    type Item = JoystickEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<JoystickEvent>> {
+        let this = self.get_mut();
+        if let Some(event) = this.pending.take() {
+            return Poll::Ready(Some(event));
+        }
+        loop {
+            match Pin::new(&mut this.events).poll_next(cx) {
+                Poll::Ready(Some(event)) => {
+                    if let Some(event) = handle_ble_event(Some(event)) {
+                        this.pending = this.battery.check(&event);
+                        return Poll::Ready(Some(event));
+                    }
+                }
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Discover the controllers matching `options.filter`, keep them connected
+/// and stream their key, Home, status and low battery events
+pub async fn joystick_events(
+    options: Options,
+) -> Result<impl Stream<Item = JoystickEvent> + Unpin, Error> {
+    let battery = BatteryMonitor::new(options.low_battery.unwrap_or(LOW_BATTERY_THRESHOLD));
+    let events = backend_events(move || {
+        let mut backend = default_backend(options.adapter.as_deref())?;
+        backend.set_scan_filter(options.scan_filter())?;
+        let scan_timeout = options.scan_timeout.unwrap_or(Duration::from_secs(10));
+        let joysticks = discover_joysticks(
+            backend.as_mut(),
+            &options.filter,
+            options.count,
+            scan_timeout,
+        )?;
+        if joysticks.is_empty() {
+            return Err(Error::DeviceNotFound("joystick".to_string()));
+        }
+
+        let mut supervisor = supervisor(BELL_SERVICE_UUID, BELL_CHAR_UUID, &options)
+            .also_notify(BATTERY_SERVICE_UUID, BATTERY_LEVEL_UUID);
+        for joystick in joysticks.iter() {
+            supervisor.add(&joystick.advertisement.id, Instant::now());
+        }
+        Ok((backend, supervisor))
+    })
+    .await?;
+    Ok(JoystickEvents::new(events, battery))
+}
+
+/// Find the thermometer matching `options.filter`, keep it connected and
+/// stream its temperature measurements
+pub async fn thermometer_events(
+    options: Options,
+) -> Result<impl Stream<Item = TemperatureMeasurement> + Unpin, Error> {
+    let events = backend_events(move || {
+        let mut backend = default_backend(options.adapter.as_deref())?;
+        backend.set_scan_filter(options.scan_filter())?;
+        let scan_timeout = options.scan_timeout.unwrap_or(Duration::from_secs(5));
+        let thermometer = find_thermometer(backend.as_mut(), &options.filter, scan_timeout)?
+            .ok_or_else(|| Error::DeviceNotFound("thermometer".to_string()))?;
+
+        let mut supervisor = supervisor(MMC_SERVICE_UUID, MMC_CHAR_UUID, &options);
+        supervisor.add(&thermometer.id, Instant::now());
+        Ok((backend, supervisor))
+    })
+    .await?;
+    Ok(events.filter_map(|event| handle_thermometer_event(Some(event))))
+}
+
+fn supervisor(service: BleUuid, characteristic: BleUuid, options: &Options) -> Supervisor {
+    Supervisor::new(service, characteristic, Backoff::default())
+        .resolve_timeout(options.resolve_timeout.unwrap_or(RESOLVE_TIMEOUT))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::{SimBackend, SimDevice};
+    use crate::joystick::DeviceStatus;
+    use async_std::task;
+
+    const BELL: &str = "E0:7D:EA:00:00:01";
+
+    fn status(connected: bool, battery: Option<u8>) -> JoystickEvent {
+        JoystickEvent::Status(BELL.to_string(), DeviceStatus { connected, battery })
+    }
+
+    #[test]
+    fn stream_controller_events() {
+        let events = task::block_on(async {
+            let events = backend_events(|| {
+                let mut sim = SimBackend::new();
+                sim.add_device(
+                    SimDevice::new(BELL, "bell").service(BELL_SERVICE_UUID, &[BELL_CHAR_UUID]),
+                )
+                .notify(BELL, BELL_CHAR_UUID, &[8, 0, 0])
+                .notify(BELL, BELL_CHAR_UUID, &[0, 0, 0]);
+                let mut supervisor =
+                    Supervisor::new(BELL_SERVICE_UUID, BELL_CHAR_UUID, Backoff::default());
+                supervisor.add(BELL, Instant::now());
+                Ok((Box::new(sim) as Box<dyn BleBackend>, supervisor))
+            })
+            .await
+            .unwrap();
+            let mut events = events.filter_map(|event| handle_ble_event(Some(event)));
+            let mut received = vec![];
+            for _ in 0..3 {
+                received.push(events.next().await.unwrap());
+            }
+            received
+        });
+        assert_eq!(
+            events,
+            vec![
+                status(true, None),
+                JoystickEvent::Home(BELL.to_string(), true),
+                JoystickEvent::Home(BELL.to_string(), false)
+            ]
+        );
+    }
+
+    #[test]
+    fn setup_error_is_returned() {
+        let r = task::block_on(backend_events(|| {
+            Err(Error::DeviceNotFound("bell".to_string()))
+        }));
+        assert!(matches!(r, Err(Error::DeviceNotFound(name)) if name == "bell"));
+    }
+
+    #[test]
+    fn stream_battery_levels() {
+        let events = task::block_on(async {
+            let events = backend_events(|| {
+                let mut sim = SimBackend::new();
+                sim.add_device(
+                    SimDevice::new(BELL, "bell")
+                        .service(BELL_SERVICE_UUID, &[BELL_CHAR_UUID])
+                        .service(BATTERY_SERVICE_UUID, &[BATTERY_LEVEL_UUID]),
+                )
+                .set_value(BELL, BATTERY_LEVEL_UUID, &[21])
+                .notify(BELL, BATTERY_LEVEL_UUID, &[19]);
+                let mut supervisor =
+                    Supervisor::new(BELL_SERVICE_UUID, BELL_CHAR_UUID, Backoff::default())
+                        .also_notify(BATTERY_SERVICE_UUID, BATTERY_LEVEL_UUID);
+                supervisor.add(BELL, Instant::now());
+                Ok((Box::new(sim) as Box<dyn BleBackend>, supervisor))
+            })
+            .await
+            .unwrap();
+            let mut events = JoystickEvents::new(events, BatteryMonitor::new(20));
+            let mut received = vec![];
+            for _ in 0..4 {
+                received.push(events.next().await.unwrap());
+            }
+            received
+        });
+        assert_eq!(
+            events,
+            vec![
+                status(true, None),
+                status(true, Some(21)),
+                status(true, Some(19)),
+                JoystickEvent::LowBattery(BELL.to_string(), 19)
+            ]
+        );
+    }
+}
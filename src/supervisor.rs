@@ -0,0 +1,499 @@
+//! Keeps controllers connected, reconnecting with exponential backoff.
+//!
+//! Each device walks Discovered → Pairing → Connecting → ResolvingServices
+//! → Subscribed. A failed step or a dropped connection moves it to Lost,
+//! from where it starts over once its backoff delay has passed.
+//!
+//! Optional characteristics, like the battery level, are subscribed and
+//! read after the main one. A device without them still counts as
+//! Subscribed.
+
+use crate::backend::{wait_services_resolved, BackendEvent, BleBackend, RESOLVE_TIMEOUT};
+use crate::ble_uuid::BleUuid;
+use crate::error::Error;
+use crate::logging::GATT;
+use log::warn;
+use rand::Rng;
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ConnectionState {
+    Discovered,
+    Pairing,
+    Connecting,
+    ResolvingServices,
+    Subscribed,
+    Lost,
+}
+
+/// A state transition of one device, for the application to show
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StateChange {
+    pub device: String,
+    pub from: ConnectionState,
+    pub to: ConnectionState,
+}
+
+/// Delay before the next attempt, `initial * factor^attempt` capped at
+/// `max`, then spread by up to ±`jitter` of itself
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub factor: f64,
+    pub jitter: f64,
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            factor: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay after `attempt` failures in a row, `attempt` starting at 0
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let base = self.initial.as_secs_f64() * self.factor.powi(attempt as i32);
+        let base = base.min(self.max.as_secs_f64());
+        let spread = if self.jitter > 0.0 {
+            rand::thread_rng().gen_range(-self.jitter, self.jitter)
+        } else {
+            0.0
+        };
+        Duration::from_secs_f64((base * (1.0 + spread)).max(0.0))
+    }
+}
+
+/// Failures in a row after which `Retry` gives up
+pub const MAX_FAILURES: u32 = 10;
+
+/// Paces a loop that keeps calling the backend: sleeps with backoff after
+/// transient errors, gives up on fatal ones or after `MAX_FAILURES` in a
+/// row
+#[derive(Clone, Debug)]
+pub struct Retry {
+    backoff: Backoff,
+    failures: u32,
+}
+
+impl Retry {
+    pub fn new(backoff: Backoff) -> Retry {
+        Retry {
+            backoff,
+            failures: 0,
+        }
+    }
+
+    pub fn succeeded(&mut self) {
+        self.failures = 0;
+    }
+
+    /// Wait before the next attempt after `e`, or return it if there
+    /// shouldn't be one
+    pub fn failed(&mut self, e: Error) -> Result<(), Error> {
+        if e.is_fatal() || self.failures + 1 >= MAX_FAILURES {
+            return Err(e);
+        }
+        thread::sleep(self.backoff.delay(self.failures));
+        self.failures += 1;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Supervised {
+    state: ConnectionState,
+    /// Failures since the last successful subscription
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Drives every added device towards Subscribed on `service` /
+/// `characteristic`
+pub struct Supervisor {
+    service: BleUuid,
+    characteristic: BleUuid,
+    /// Service and characteristic pairs subscribed if the device has them
+    optional: Vec<(BleUuid, BleUuid)>,
+    backoff: Backoff,
+    resolve_timeout: Duration,
+    devices: HashMap<String, Supervised>,
+    /// Events of other devices that arrived while resolving services
+    events: Vec<BackendEvent>,
+}
+
+impl Supervisor {
+    pub fn new(service: BleUuid, characteristic: BleUuid, backoff: Backoff) -> Supervisor {
+        Supervisor {
+            service,
+            characteristic,
+            optional: vec![],
+            backoff,
+            resolve_timeout: RESOLVE_TIMEOUT,
+            devices: HashMap::new(),
+            events: vec![],
+        }
+    }
+
+    /// How long to wait for the services after connecting
+    pub fn resolve_timeout(mut self, timeout: Duration) -> Supervisor {
+        self.resolve_timeout = timeout;
+        self
+    }
+
+    /// Also subscribe to `characteristic` and read it once after each
+    /// connection, the value arrives as a notification event. Failing
+    /// to do so is not a failed attempt.
+    pub fn also_notify(mut self, service: BleUuid, characteristic: BleUuid) -> Supervisor {
+        self.optional.push((service, characteristic));
+        self
+    }
+
+    /// Start supervising a device, a no-op if it already is
+    pub fn add(&mut self, device: &str, now: Instant) {
+        self.devices
+            .entry(device.to_string())
+            .or_insert(Supervised {
+                state: ConnectionState::Discovered,
+                failures: 0,
+                retry_at: now,
+            });
+    }
+
+    pub fn state(&self, device: &str) -> Option<ConnectionState> {
+        self.devices.get(device).map(|d| d.state)
+    }
+
+    /// Earliest time a device is due for another attempt
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.devices
+            .values()
+            .filter(|d| is_idle(d.state))
+            .map(|d| d.retry_at)
+            .min()
+    }
+
+    /// Try to bring up every device that is due
+    pub fn poll<B: BleBackend + ?Sized>(
+        &mut self,
+        backend: &mut B,
+        now: Instant,
+    ) -> Vec<StateChange> {
+        let mut due = self
+            .devices
+            .iter()
+            .filter(|(_, d)| is_idle(d.state) && d.retry_at <= now)
+            .map(|(device, _)| device.clone())
+            .collect::<Vec<_>>();
+        due.sort();
+
+        let mut changes = vec![];
+        for device in due {
+            self.bring_up(backend, &device, now, &mut changes);
+        }
+        changes
+    }
+
+    /// Events read from the backend during `poll` that are not about
+    /// connecting, to be handled like those from `next_event`
+    pub fn take_events(&mut self) -> Vec<BackendEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Track disconnects reported by the backend
+    pub fn handle_event(&mut self, event: &BackendEvent, now: Instant) -> Vec<StateChange> {
+        let mut changes = vec![];
+        if let BackendEvent::Connected {
+            device,
+            connected: false,
+        } = event
+        {
+            if let Some(d) = self.devices.get(device) {
+                if !is_idle(d.state) {
+                    self.lose(device, now, &mut changes);
+                }
+            }
+        }
+        changes
+    }
+
+    fn bring_up<B: BleBackend + ?Sized>(
+        &mut self,
+        backend: &mut B,
+        device: &str,
+        now: Instant,
+        changes: &mut Vec<StateChange>,
+    ) {
+        self.set(device, ConnectionState::Pairing, changes);
+        if backend.pair(device).is_err() {
+            return self.lose(device, now, changes);
+        }
+
+        self.set(device, ConnectionState::Connecting, changes);
+        if backend.connect(device).is_err() {
+            return self.lose(device, now, changes);
+        }
+
+        self.set(device, ConnectionState::ResolvingServices, changes);
+        match wait_services_resolved(backend, device, self.resolve_timeout) {
+            Ok(events) => self.events.extend(events),
+            Err(_) => {
+                // 断开, 下次从头连接
+                backend.disconnect(device).ok();
+                return self.lose(device, now, changes);
+            }
+        }
+
+        // 每次重连之后都要重新打开通知
+        if backend
+            .subscribe(device, self.service, self.characteristic)
+            .is_err()
+        {
+            return self.lose(device, now, changes);
+        }
+
+        self.subscribe_optional(backend, device);
+
+        self.set(device, ConnectionState::Subscribed, changes);
+        if let Some(d) = self.devices.get_mut(device) {
+            d.failures = 0;
+        }
+    }
+
+    fn subscribe_optional<B: BleBackend + ?Sized>(&mut self, backend: &mut B, device: &str) {
+        for &(service, characteristic) in self.optional.iter() {
+            if let Err(e) = backend.subscribe(device, service, characteristic) {
+                warn!(target: GATT, "{}: no notifications on {}: {}", device, characteristic, e);
+                continue;
+            }
+            // 通知只在变化时才发, 先读一次当前值
+            match backend.read_characteristic(device, service, characteristic) {
+                Ok(value) if !value.is_empty() => self.events.push(BackendEvent::Notification {
+                    device: device.to_string(),
+                    characteristic: Some(characteristic),
+                    value,
+                }),
+                Ok(_) => {}
+                Err(e) => {
+                    warn!(target: GATT, "{}: failed to read {}: {}", device, characteristic, e)
+                }
+            }
+        }
+    }
+
+    fn lose(&mut self, device: &str, now: Instant, changes: &mut Vec<StateChange>) {
+        self.set(device, ConnectionState::Lost, changes);
+        if let Some(d) = self.devices.get_mut(device) {
+            d.retry_at = now + self.backoff.delay(d.failures);
+            d.failures += 1;
+        }
+    }
+
+    fn set(&mut self, device: &str, to: ConnectionState, changes: &mut Vec<StateChange>) {
+        if let Some(d) = self.devices.get_mut(device) {
+            if d.state != to {
+                changes.push(StateChange {
+                    device: device.to_string(),
+                    from: d.state,
+                    to,
+                });
+                d.state = to;
+            }
+        }
+    }
+}
+
+/// Waiting for the next attempt
+fn is_idle(state: ConnectionState) -> bool {
+    state == ConnectionState::Discovered || state == ConnectionState::Lost
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::{SimBackend, SimDevice};
+    use ConnectionState::*;
+
+    const BELL: &str = "E0:7D:EA:00:00:01";
+    const OTHER: &str = "E0:7D:EA:00:00:02";
+    const SERVICE: BleUuid = BleUuid::from_u16(0x8850);
+    const CHAR: BleUuid = BleUuid::from_u16(0x885a);
+    const BATTERY: BleUuid = BleUuid::from_u16(0x180f);
+    const LEVEL: BleUuid = BleUuid::from_u16(0x2a19);
+
+    fn backoff() -> Backoff {
+        Backoff {
+            jitter: 0.0,
+            ..Default::default()
+        }
+    }
+
+    fn states(changes: &[StateChange]) -> Vec<ConnectionState> {
+        changes.iter().map(|c| c.to).collect()
+    }
+
+    #[test]
+    fn backoff_grows_and_is_capped() {
+        let b = backoff();
+        assert_eq!(b.delay(0), Duration::from_secs(1));
+        assert_eq!(b.delay(3), Duration::from_secs(8));
+        assert_eq!(b.delay(20), Duration::from_secs(60));
+
+        let jittered = Backoff::default();
+        for _ in 0..100 {
+            let d = jittered.delay(2).as_secs_f64();
+            assert!((3.2..=4.8).contains(&d));
+        }
+    }
+
+    #[test]
+    fn retry_gives_up_on_fatal_errors_and_repeated_failures() {
+        let mut retry = Retry::new(Backoff {
+            initial: Duration::from_secs(0),
+            ..backoff()
+        });
+        assert!(retry
+            .failed(Error::Closed("Event channel".to_string()))
+            .is_err());
+
+        for _ in 1..MAX_FAILURES {
+            assert!(retry.failed(Error::Backend("busy".to_string())).is_ok());
+        }
+        assert!(retry.failed(Error::Backend("busy".to_string())).is_err());
+
+        retry.succeeded();
+        assert!(retry.failed(Error::Backend("busy".to_string())).is_ok());
+    }
+
+    #[test]
+    fn reconnects_and_resubscribes_after_drop() {
+        let mut sim = SimBackend::new();
+        sim.add_device(SimDevice::new(BELL, "bell").service(SERVICE, &[CHAR]));
+        let mut supervisor = Supervisor::new(SERVICE, CHAR, backoff());
+        let start = Instant::now();
+
+        supervisor.add(BELL, start);
+        assert_eq!(
+            states(&supervisor.poll(&mut sim, start)),
+            vec![Pairing, Connecting, ResolvingServices, Subscribed]
+        );
+        assert!(sim.is_subscribed(BELL, CHAR));
+        assert_eq!(
+            supervisor.take_events(),
+            vec![BackendEvent::Connected {
+                device: BELL.to_string(),
+                connected: true
+            }]
+        );
+
+        sim.drop_connection(BELL);
+        let mut changes = vec![];
+        while let Some(event) = sim.next_event(Duration::from_secs(1)).unwrap() {
+            changes.extend(supervisor.handle_event(&event, start));
+        }
+        assert_eq!(states(&changes), vec![Lost]);
+        assert!(!sim.is_subscribed(BELL, CHAR));
+
+        assert!(supervisor.poll(&mut sim, start).is_empty());
+        let retry = supervisor.next_retry().unwrap();
+        assert_eq!(retry, start + Duration::from_secs(1));
+        assert_eq!(
+            states(&supervisor.poll(&mut sim, retry)),
+            vec![Pairing, Connecting, ResolvingServices, Subscribed]
+        );
+        assert!(sim.is_subscribed(BELL, CHAR));
+    }
+
+    #[test]
+    fn failed_connects_back_off() {
+        let mut sim = SimBackend::new();
+        sim.add_device(
+            SimDevice::new(BELL, "bell")
+                .service(SERVICE, &[CHAR])
+                .connect_error("Page timeout"),
+        );
+        let mut supervisor = Supervisor::new(SERVICE, CHAR, backoff());
+        let start = Instant::now();
+        supervisor.add(BELL, start);
+
+        let mut now = start;
+        for delay in [1, 2, 4].iter() {
+            assert_eq!(
+                states(&supervisor.poll(&mut sim, now)),
+                vec![Pairing, Connecting, Lost]
+            );
+            assert_eq!(supervisor.state(BELL), Some(Lost));
+            now += Duration::from_secs(*delay);
+            assert_eq!(supervisor.next_retry(), Some(now));
+        }
+
+        sim.set_connect_error(BELL, None);
+        assert_eq!(
+            states(&supervisor.poll(&mut sim, now)),
+            vec![Pairing, Connecting, ResolvingServices, Subscribed]
+        );
+    }
+
+    #[test]
+    fn unresolved_services_are_a_failed_attempt() {
+        let mut sim = SimBackend::new();
+        sim.add_device(
+            SimDevice::new(BELL, "bell")
+                .service(SERVICE, &[CHAR])
+                .never_resolves(),
+        );
+        let mut supervisor =
+            Supervisor::new(SERVICE, CHAR, backoff()).resolve_timeout(Duration::from_millis(10));
+        let start = Instant::now();
+        supervisor.add(BELL, start);
+
+        assert_eq!(
+            states(&supervisor.poll(&mut sim, start)),
+            vec![Pairing, Connecting, ResolvingServices, Lost]
+        );
+        assert!(!sim.is_connected(BELL));
+        assert!(!sim.is_subscribed(BELL, CHAR));
+    }
+
+    #[test]
+    fn optional_characteristics_are_read_and_subscribed() {
+        let mut sim = SimBackend::new();
+        sim.add_device(
+            SimDevice::new(BELL, "bell")
+                .service(SERVICE, &[CHAR])
+                .service(BATTERY, &[LEVEL]),
+        )
+        .set_value(BELL, LEVEL, &[87]);
+        let mut supervisor = Supervisor::new(SERVICE, CHAR, backoff()).also_notify(BATTERY, LEVEL);
+        supervisor.add(BELL, Instant::now());
+
+        assert_eq!(
+            states(&supervisor.poll(&mut sim, Instant::now())),
+            vec![Pairing, Connecting, ResolvingServices, Subscribed]
+        );
+        assert!(sim.is_subscribed(BELL, LEVEL));
+        assert!(supervisor
+            .take_events()
+            .contains(&BackendEvent::Notification {
+                device: BELL.to_string(),
+                characteristic: Some(LEVEL),
+                value: vec![87],
+            }));
+
+        // 没有电池服务的设备照样能连上
+        sim.add_device(SimDevice::new(OTHER, "bell").service(SERVICE, &[CHAR]));
+        supervisor.add(OTHER, Instant::now());
+        assert_eq!(
+            states(&supervisor.poll(&mut sim, Instant::now())),
+            vec![Pairing, Connecting, ResolvingServices, Subscribed]
+        );
+        assert!(!sim.is_subscribed(OTHER, LEVEL));
+    }
+}
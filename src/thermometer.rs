@@ -0,0 +1,431 @@
+//! Health Thermometer decoding, plus the MMC calibration.
+
+use crate::backend::{Advertisement, BackendEvent, BleBackend};
+use crate::ble_uuid::BleUuid;
+use crate::discovery::{find_device, DeviceFilter};
+use crate::error::Error;
+use crate::logging::DECODE;
+use log::{trace, warn};
+use std::fmt;
+use std::time::Duration;
+
+pub const MMC_SERVICE_UUID: BleUuid = BleUuid::from_u16(0x1809);
+pub const MMC_CHAR_UUID: BleUuid = BleUuid::from_u16(0x2a1e);
+pub const TEMPERATURE_MEASUREMENT_UUID: BleUuid = BleUuid::from_u16(0x2a1c);
+
+// 温度测量的 flags 位
+const FLAG_FAHRENHEIT: u8 = 0x01;
+const FLAG_TIMESTAMP: u8 = 0x02;
+const FLAG_TEMPERATURE_TYPE: u8 = 0x04;
+
+// IEEE 11073 FLOAT 的特殊值, 指数为 0
+const FLOAT_NAN: i32 = 0x007f_ffff;
+const FLOAT_NRES: i32 = -0x0080_0000;
+const FLOAT_POSITIVE_INFINITY: i32 = 0x007f_fffe;
+const FLOAT_NEGATIVE_INFINITY: i32 = -0x007f_fffe;
+const FLOAT_RESERVED: i32 = -0x007f_ffff;
+
+/// IEEE 11073-20601 32 bit FLOAT, a 24 bit mantissa and 8 bit exponent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MedFloat {
+    Value(f64),
+    NaN,
+    /// Not at this resolution
+    NRes,
+    PositiveInfinity,
+    NegativeInfinity,
+    Reserved,
+}
+
+impl MedFloat {
+    pub fn from_le_bytes(bytes: [u8; 4]) -> MedFloat {
+        let raw = u32::from_le_bytes(bytes);
+        let exponent = (raw >> 24) as i8;
+        // 24 位有符号尾数
+        let mantissa = ((raw << 8) as i32) >> 8;
+
+        if exponent == 0 {
+            match mantissa {
+                FLOAT_NAN => return MedFloat::NaN,
+                FLOAT_NRES => return MedFloat::NRes,
+                FLOAT_POSITIVE_INFINITY => return MedFloat::PositiveInfinity,
+                FLOAT_NEGATIVE_INFINITY => return MedFloat::NegativeInfinity,
+                FLOAT_RESERVED => return MedFloat::Reserved,
+                _ => {}
+            }
+        }
+        MedFloat::Value(mantissa as f64 * 10f64.powi(exponent as i32))
+    }
+
+    /// The number, `None` for the special values
+    pub fn value(self) -> Option<f64> {
+        match self {
+            MedFloat::Value(v) => Some(v),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+/// Date Time characteristic layout, 0 for unknown fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+}
+
+/// Where the temperature was taken, Temperature Type characteristic
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TemperatureType {
+    Armpit,
+    Body,
+    Ear,
+    Finger,
+    GastroIntestinalTract,
+    Mouth,
+    Rectum,
+    Toe,
+    Tympanum,
+    Reserved(u8),
+}
+
+impl TemperatureType {
+    pub fn from_byte(value: u8) -> TemperatureType {
+        match value {
+            1 => TemperatureType::Armpit,
+            2 => TemperatureType::Body,
+            3 => TemperatureType::Ear,
+            4 => TemperatureType::Finger,
+            5 => TemperatureType::GastroIntestinalTract,
+            6 => TemperatureType::Mouth,
+            7 => TemperatureType::Rectum,
+            8 => TemperatureType::Toe,
+            9 => TemperatureType::Tympanum,
+            v => TemperatureType::Reserved(v),
+        }
+    }
+}
+
+/// A decoded Temperature Measurement (2a1c) or Intermediate Temperature
+/// (2a1e) value
+#[derive(Clone, Debug, PartialEq)]
+pub struct TemperatureMeasurement {
+    pub temperature: MedFloat,
+    pub unit: TemperatureUnit,
+    pub timestamp: Option<Timestamp>,
+    pub temperature_type: Option<TemperatureType>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MeasurementError {
+    /// Shorter than the fields announced by the flags
+    InvalidLength { expected: usize, actual: usize },
+}
+
+impl fmt::Display for MeasurementError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MeasurementError::InvalidLength { expected, actual } => write!(
+                f,
+                "invalid measurement length {}, expected {}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MeasurementError {}
+
+impl TemperatureMeasurement {
+    /// Decode a raw value as the Health Thermometer service specifies it.
+    /// Extra trailing bytes are ignored.
+    pub fn parse(value: &[u8]) -> Result<TemperatureMeasurement, MeasurementError> {
+        let flags = *value.first().ok_or(MeasurementError::InvalidLength {
+            expected: 5,
+            actual: 0,
+        })?;
+        let expected = 5
+            + if flags & FLAG_TIMESTAMP > 0 { 7 } else { 0 }
+            + if flags & FLAG_TEMPERATURE_TYPE > 0 {
+                1
+            } else {
+                0
+            };
+        if value.len() < expected {
+            return Err(MeasurementError::InvalidLength {
+                expected,
+                actual: value.len(),
+            });
+        }
+
+        let temperature = MedFloat::from_le_bytes([value[1], value[2], value[3], value[4]]);
+        let mut rest = &value[5..];
+
+        let timestamp = if flags & FLAG_TIMESTAMP > 0 {
+            let t = Timestamp {
+                year: u16::from_le_bytes([rest[0], rest[1]]),
+                month: rest[2],
+                day: rest[3],
+                hours: rest[4],
+                minutes: rest[5],
+                seconds: rest[6],
+            };
+            rest = &rest[7..];
+            Some(t)
+        } else {
+            None
+        };
+
+        let temperature_type = if flags & FLAG_TEMPERATURE_TYPE > 0 {
+            Some(TemperatureType::from_byte(rest[0]))
+        } else {
+            None
+        };
+
+        Ok(TemperatureMeasurement {
+            temperature,
+            unit: if flags & FLAG_FAHRENHEIT > 0 {
+                TemperatureUnit::Fahrenheit
+            } else {
+                TemperatureUnit::Celsius
+            },
+            timestamp,
+            temperature_type,
+        })
+    }
+
+    /// The temperature in Celsius, `None` for the special values
+    pub fn celsius(&self) -> Option<f64> {
+        let t = self.temperature.value()?;
+        match self.unit {
+            TemperatureUnit::Celsius => Some(t),
+            TemperatureUnit::Fahrenheit => Some((t - 32.0) * 5.0 / 9.0),
+        }
+    }
+}
+
+/// Scan until the thermometer matching `filter` shows up, see `find_device`
+pub fn find_thermometer<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    filter: &DeviceFilter,
+    timeout: Duration,
+) -> Result<Option<Advertisement>, Error> {
+    find_device(backend, filter, timeout)
+}
+
+/// Enable temperature notifications on a connected thermometer
+pub fn enable_thermometer_notify<B: BleBackend + ?Sized>(
+    backend: &mut B,
+    device: &str,
+) -> Result<(), Error> {
+    backend.subscribe(device, MMC_SERVICE_UUID, MMC_CHAR_UUID)
+}
+
+/// Temperature Measurement or Intermediate Temperature, `None` counts as
+/// one of them for backends that don't report the characteristic
+pub fn is_temperature_characteristic(characteristic: Option<BleUuid>) -> bool {
+    match characteristic {
+        Some(uuid) => uuid == TEMPERATURE_MEASUREMENT_UUID || uuid == MMC_CHAR_UUID,
+        None => true,
+    }
+}
+
+/// Decode a temperature notification as specified, see
+/// `TemperatureMeasurement::parse`. Notifications of other
+/// characteristics are skipped.
+pub fn handle_thermometer_event(event: Option<BackendEvent>) -> Option<TemperatureMeasurement> {
+    match event? {
+        BackendEvent::Notification {
+            device,
+            characteristic,
+            value,
+        } if is_temperature_characteristic(characteristic) => {
+            trace!(target: DECODE, "{} {:x?}", device, value);
+            match TemperatureMeasurement::parse(&value) {
+                Ok(measurement) => Some(measurement),
+                Err(e) => {
+                    warn!(target: DECODE, "Invalid measurement from {}: {}", device, e);
+                    None
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Decode a temperature notification with the MMC calibration, see
+/// `mmc_calibrate`
+pub fn handle_mmc_event(event: Option<BackendEvent>) -> Option<(f32, f32, f32, f32)> {
+    match event? {
+        BackendEvent::Notification { value, .. } => mmc_calibrate(&value),
+        _ => None,
+    }
+}
+
+/// Vendor calibration of the MMC thermometer, which does not follow the
+/// Health Thermometer layout. Takes its 6 byte notification and returns
+/// `(t0, t1, toff, t4)`, where `t0` is the raw temperature and `t4` the
+/// calibrated one, all in Celsius.
+pub fn mmc_calibrate(data: &[u8]) -> Option<(f32, f32, f32, f32)> {
+    if data.len() == 6 {
+        let mut t0: f32 = data[2] as f32 * 256.0 + data[1] as f32;
+        let mut offset: f32 = 0.0;
+        let mut t1: f32 = 0.0;
+        let mut t4: f32 = t0;
+
+        if data[3] != 0xf2 || data[4] != 0x7f {
+            t1 = data[4] as f32 * 256.0 + data[3] as f32;
+            let diff = t0 - t1;
+            offset = diff / 2.0;
+
+            if diff > 0.0 {
+                let mut off = diff;
+                while off > 200.0 {
+                    off -= 100.0;
+                }
+
+                if off < 100.0 {
+                    off += 50.0;
+                }
+
+                t4 = t0 + off;
+            }
+
+            if offset > 1.0 {
+                offset = 1.0;
+            }
+        }
+
+        let mut toff = t0 + offset;
+        t1 /= 100.0;
+        t0 /= 100.0;
+        toff /= 100.0;
+        t4 /= 100.0;
+
+        trace!(target: DECODE, "t0: {}, t1: {}, toff: {}, t4: {}", t0, t1, toff, t4);
+        return Some((t0, t1, toff, t4));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::sim::{SimBackend, SimDevice};
+    use std::time::Duration;
+
+    const MMC: &str = "00:81:F9:DF:B0:40";
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn parse_celsius_measurement() {
+        // 3698 * 10^-2
+        let m = TemperatureMeasurement::parse(&[0x00, 0x72, 0x0e, 0x00, 0xfe]).unwrap();
+        assert_eq!(m.unit, TemperatureUnit::Celsius);
+        assert!(close(m.celsius().unwrap(), 36.98));
+        assert_eq!(m.timestamp, None);
+        assert_eq!(m.temperature_type, None);
+    }
+
+    #[test]
+    fn parse_fahrenheit_with_timestamp_and_type() {
+        let value = [
+            0x07, 0xda, 0x03, 0x00, 0xff, 0xe4, 0x07, 0x08, 0x01, 0x0c, 0x1e, 0x2d, 0x06,
+        ];
+        let m = TemperatureMeasurement::parse(&value).unwrap();
+        assert_eq!(m.unit, TemperatureUnit::Fahrenheit);
+        assert!(close(m.temperature.value().unwrap(), 98.6));
+        assert!(close(m.celsius().unwrap(), 37.0));
+        assert_eq!(
+            m.timestamp,
+            Some(Timestamp {
+                year: 2020,
+                month: 8,
+                day: 1,
+                hours: 12,
+                minutes: 30,
+                seconds: 45,
+            })
+        );
+        assert_eq!(m.temperature_type, Some(TemperatureType::Mouth));
+    }
+
+    #[test]
+    fn parse_special_values() {
+        let expected = [
+            ([0xff, 0xff, 0x7f, 0x00], MedFloat::NaN),
+            ([0x00, 0x00, 0x80, 0x00], MedFloat::NRes),
+            ([0xfe, 0xff, 0x7f, 0x00], MedFloat::PositiveInfinity),
+            ([0x02, 0x00, 0x80, 0x00], MedFloat::NegativeInfinity),
+            ([0x01, 0x00, 0x80, 0x00], MedFloat::Reserved),
+            ([0xff, 0xff, 0xff, 0x00], MedFloat::Value(-1.0)),
+        ];
+        for (bytes, float) in expected.iter() {
+            assert_eq!(MedFloat::from_le_bytes(*bytes), *float);
+        }
+
+        let m = TemperatureMeasurement::parse(&[0x00, 0xff, 0xff, 0x7f, 0x00]).unwrap();
+        assert_eq!(m.celsius(), None);
+    }
+
+    #[test]
+    fn parse_truncated_measurement() {
+        assert_eq!(
+            TemperatureMeasurement::parse(&[0x02, 0x72, 0x0e, 0x00, 0xfe]),
+            Err(MeasurementError::InvalidLength {
+                expected: 12,
+                actual: 5
+            })
+        );
+        assert_eq!(
+            TemperatureMeasurement::parse(&[]),
+            Err(MeasurementError::InvalidLength {
+                expected: 5,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn only_temperature_characteristics_are_decoded() {
+        let event = |characteristic| {
+            Some(BackendEvent::Notification {
+                device: MMC.to_string(),
+                characteristic,
+                value: vec![0x00, 0x72, 0x0e, 0x00, 0xfe],
+            })
+        };
+        assert!(handle_thermometer_event(event(Some(TEMPERATURE_MEASUREMENT_UUID))).is_some());
+        assert!(handle_thermometer_event(event(Some(MMC_CHAR_UUID))).is_some());
+        assert!(handle_thermometer_event(event(None)).is_some());
+        assert!(handle_thermometer_event(event(Some(BleUuid::from_u16(0x2a19)))).is_none());
+    }
+
+    #[test]
+    fn thermometer_notifications_are_decoded() {
+        let mut sim = SimBackend::new();
+        sim.add_device(SimDevice::new(MMC, "MMC").service(MMC_SERVICE_UUID, &[MMC_CHAR_UUID]))
+            .notify(MMC, MMC_CHAR_UUID, &[0x00, 0x10, 0x0e, 0xf2, 0x7f, 0x00]);
+
+        sim.connect(MMC).unwrap();
+        enable_thermometer_notify(&mut sim, MMC).unwrap();
+
+        let mut temperatures = vec![];
+        while let Some(event) = sim.next_event(Duration::from_secs(1)).unwrap() {
+            temperatures.extend(handle_mmc_event(Some(event)));
+        }
+        assert_eq!(temperatures, vec![(36.0, 0.0, 36.0, 36.0)]);
+    }
+}
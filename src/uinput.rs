@@ -0,0 +1,314 @@
+//! Linux uinput virtual gamepad fed by the joystick edge events.
+
+use crate::input::{Axis, Button, InputEvent};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::slice;
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_ABS: u16 = 0x03;
+
+pub const SYN_REPORT: u16 = 0;
+
+pub const BTN_SOUTH: u16 = 0x130;
+pub const BTN_EAST: u16 = 0x131;
+pub const BTN_NORTH: u16 = 0x133;
+pub const BTN_WEST: u16 = 0x134;
+pub const BTN_TL: u16 = 0x136;
+pub const BTN_TR: u16 = 0x137;
+pub const BTN_TL2: u16 = 0x138;
+pub const BTN_TR2: u16 = 0x139;
+pub const BTN_SELECT: u16 = 0x13a;
+pub const BTN_START: u16 = 0x13b;
+pub const BTN_MODE: u16 = 0x13c;
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_Z: u16 = 0x02;
+pub const ABS_RX: u16 = 0x03;
+pub const ABS_RY: u16 = 0x04;
+pub const ABS_RZ: u16 = 0x05;
+pub const ABS_HAT0X: u16 = 0x10;
+pub const ABS_HAT0Y: u16 = 0x11;
+
+const BUS_BLUETOOTH: u16 = 0x05;
+
+// linux/uinput.h, _IO('U', nr) 和 _IOW('U', nr, size)
+const fn uinput_ioctl(write: bool, nr: u64, size: usize) -> u64 {
+    let dir = if write { 1 << 30 } else { 0 };
+    dir | ((size as u64) << 16) | (0x55 << 8) | nr
+}
+
+const UI_DEV_CREATE: u64 = uinput_ioctl(false, 1, 0);
+const UI_DEV_DESTROY: u64 = uinput_ioctl(false, 2, 0);
+const UI_DEV_SETUP: u64 = uinput_ioctl(true, 3, mem::size_of::<libc::uinput_setup>());
+const UI_ABS_SETUP: u64 = uinput_ioctl(true, 4, mem::size_of::<libc::uinput_abs_setup>());
+const UI_SET_EVBIT: u64 = uinput_ioctl(true, 100, mem::size_of::<libc::c_int>());
+const UI_SET_KEYBIT: u64 = uinput_ioctl(true, 101, mem::size_of::<libc::c_int>());
+const UI_SET_ABSBIT: u64 = uinput_ioctl(true, 103, mem::size_of::<libc::c_int>());
+
+/// Key code of every joystick button
+pub fn button_code(button: Button) -> u16 {
+    match button {
+        Button::A => BTN_SOUTH,
+        Button::B => BTN_EAST,
+        Button::C => BTN_WEST,
+        Button::D => BTN_NORTH,
+        Button::I => BTN_SELECT,
+        Button::II => BTN_START,
+        Button::L1 => BTN_TL,
+        Button::R1 => BTN_TR,
+        Button::L2 => BTN_TL2,
+        Button::R2 => BTN_TR2,
+        Button::Home => BTN_MODE,
+    }
+}
+
+/// Absolute axis code of every analog input
+pub fn axis_code(axis: Axis) -> u16 {
+    match axis {
+        Axis::RlX => ABS_X,
+        Axis::RlY => ABS_Y,
+        Axis::RrX => ABS_RX,
+        Axis::RrY => ABS_RY,
+        Axis::L2 => ABS_Z,
+        Axis::R2 => ABS_RZ,
+    }
+}
+
+const BUTTONS: [Button; 11] = [
+    Button::A,
+    Button::B,
+    Button::C,
+    Button::D,
+    Button::I,
+    Button::II,
+    Button::L1,
+    Button::R1,
+    Button::L2,
+    Button::R2,
+    Button::Home,
+];
+
+/// Value of `axis` before the first report, the knobs rest in the middle
+pub fn initial_value(axis: Axis) -> i32 {
+    match axis {
+        Axis::RlX | Axis::RlY | Axis::RrX | Axis::RrY => 0x80,
+        Axis::L2 | Axis::R2 => 0,
+    }
+}
+
+const AXES: [Axis; 6] = [
+    Axis::RlX,
+    Axis::RlY,
+    Axis::RrX,
+    Axis::RrY,
+    Axis::L2,
+    Axis::R2,
+];
+
+/// One `input_event` without the timestamp, the kernel fills it in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RawEvent {
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Destination of the raw events, the uinput device or a recorder in tests
+pub trait EventWriter {
+    fn write_event(&mut self, event: RawEvent) -> io::Result<()>;
+}
+
+impl EventWriter for Vec<RawEvent> {
+    fn write_event(&mut self, event: RawEvent) -> io::Result<()> {
+        self.push(event);
+        Ok(())
+    }
+}
+
+/// A gamepad created through /dev/uinput, destroyed on drop
+pub struct UinputDevice {
+    file: File,
+}
+
+fn ioctl<T>(file: &File, request: u64, arg: T) -> io::Result<()> {
+    let r = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg) };
+    if r < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl UinputDevice {
+    /// Register a gamepad with the Bell controller capabilities
+    pub fn create(name: &str) -> io::Result<UinputDevice> {
+        let file = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")?;
+
+        ioctl(&file, UI_SET_EVBIT, EV_KEY as libc::c_int)?;
+        for button in BUTTONS.iter() {
+            ioctl(&file, UI_SET_KEYBIT, button_code(*button) as libc::c_int)?;
+        }
+
+        ioctl(&file, UI_SET_EVBIT, EV_ABS as libc::c_int)?;
+        let mut abs = AXES
+            .iter()
+            .map(|axis| (axis_code(*axis), 0, 255, initial_value(*axis)))
+            .collect::<Vec<_>>();
+        abs.push((ABS_HAT0X, -1, 1, 0));
+        abs.push((ABS_HAT0Y, -1, 1, 0));
+        for (code, minimum, maximum, value) in abs {
+            ioctl(&file, UI_SET_ABSBIT, code as libc::c_int)?;
+            let mut setup: libc::uinput_abs_setup = unsafe { mem::zeroed() };
+            setup.code = code;
+            setup.absinfo.minimum = minimum;
+            setup.absinfo.maximum = maximum;
+            setup.absinfo.value = value;
+            ioctl(&file, UI_ABS_SETUP, &setup as *const libc::uinput_abs_setup)?;
+        }
+
+        let mut setup: libc::uinput_setup = unsafe { mem::zeroed() };
+        setup.id.bustype = BUS_BLUETOOTH;
+        for (dst, src) in setup
+            .name
+            .iter_mut()
+            .zip(name.bytes().take(libc::UINPUT_MAX_NAME_SIZE - 1))
+        {
+            *dst = src as libc::c_char;
+        }
+        ioctl(&file, UI_DEV_SETUP, &setup as *const libc::uinput_setup)?;
+        ioctl(&file, UI_DEV_CREATE, 0)?;
+
+        Ok(UinputDevice { file })
+    }
+}
+
+impl EventWriter for UinputDevice {
+    fn write_event(&mut self, event: RawEvent) -> io::Result<()> {
+        let mut raw: libc::input_event = unsafe { mem::zeroed() };
+        raw.type_ = event.type_;
+        raw.code = event.code;
+        raw.value = event.value;
+        let bytes = unsafe {
+            slice::from_raw_parts(
+                &raw as *const libc::input_event as *const u8,
+                mem::size_of::<libc::input_event>(),
+            )
+        };
+        self.file.write_all(bytes)
+    }
+}
+
+impl Drop for UinputDevice {
+    fn drop(&mut self) {
+        ioctl(&self.file, UI_DEV_DESTROY, 0).ok();
+    }
+}
+
+/// Translates edge events into evdev events, one SYN_REPORT per batch
+pub struct VirtualGamepad<W: EventWriter> {
+    writer: W,
+}
+
+impl<W: EventWriter> VirtualGamepad<W> {
+    pub fn new(writer: W) -> VirtualGamepad<W> {
+        VirtualGamepad { writer }
+    }
+
+    pub fn writer(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn emit(&mut self, events: &[InputEvent]) -> io::Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        for event in events {
+            match event {
+                InputEvent::ButtonDown(button) => self.write(EV_KEY, button_code(*button), 1)?,
+                InputEvent::ButtonUp(button) => self.write(EV_KEY, button_code(*button), 0)?,
+                InputEvent::AxisChanged(axis, value) => {
+                    self.write(EV_ABS, axis_code(*axis), *value as i32)?
+                }
+                InputEvent::HatChanged(hat) => {
+                    self.write(EV_ABS, ABS_HAT0X, hat.x() as i32)?;
+                    self.write(EV_ABS, ABS_HAT0Y, hat.y() as i32)?;
+                }
+            }
+        }
+        self.write(EV_SYN, SYN_REPORT, 0)
+    }
+
+    fn write(&mut self, type_: u16, code: u16, value: i32) -> io::Result<()> {
+        self.writer.write_event(RawEvent { type_, code, value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::joystick::Hat;
+
+    fn raw(type_: u16, code: u16, value: i32) -> RawEvent {
+        RawEvent { type_, code, value }
+    }
+
+    #[test]
+    fn buttons_and_axes_are_synced() {
+        let mut pad = VirtualGamepad::new(vec![]);
+        pad.emit(&[
+            InputEvent::ButtonDown(Button::A),
+            InputEvent::ButtonDown(Button::Home),
+            InputEvent::AxisChanged(Axis::L2, 0x7f),
+        ])
+        .unwrap();
+        pad.emit(&[InputEvent::ButtonUp(Button::A)]).unwrap();
+
+        assert_eq!(
+            pad.writer(),
+            &vec![
+                raw(EV_KEY, BTN_SOUTH, 1),
+                raw(EV_KEY, BTN_MODE, 1),
+                raw(EV_ABS, ABS_Z, 0x7f),
+                raw(EV_SYN, SYN_REPORT, 0),
+                raw(EV_KEY, BTN_SOUTH, 0),
+                raw(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn hat_maps_to_both_hat_axes() {
+        let mut pad = VirtualGamepad::new(vec![]);
+        pad.emit(&[InputEvent::HatChanged(Hat::DownLeft)]).unwrap();
+        pad.emit(&[]).unwrap();
+
+        assert_eq!(
+            pad.writer(),
+            &vec![
+                raw(EV_ABS, ABS_HAT0X, -1),
+                raw(EV_ABS, ABS_HAT0Y, 1),
+                raw(EV_SYN, SYN_REPORT, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn knobs_start_centered() {
+        assert_eq!(initial_value(Axis::RlX), 0x80);
+        assert_eq!(initial_value(Axis::RlY), 0x80);
+        assert_eq!(initial_value(Axis::RrX), 0x80);
+        assert_eq!(initial_value(Axis::RrY), 0x80);
+        assert_eq!(initial_value(Axis::L2), 0);
+        assert_eq!(initial_value(Axis::R2), 0);
+    }
+}